@@ -0,0 +1,553 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::emulator::PROGRAM_ADDRESS;
+
+#[doc = "Error found while assembling, pointing at the offending source line"]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssemblerError {
+    pub file: PathBuf,
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AssemblerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.file.display(), self.line, self.message)
+    }
+}
+
+impl std::error::Error for AssemblerError {}
+
+#[derive(Debug)]
+enum StatementKind {
+    Instruction { mnemonic: String, operands: Vec<String> },
+    Bytes(Vec<String>),
+    Words(Vec<String>),
+}
+
+#[derive(Debug)]
+struct Statement {
+    file: PathBuf,
+    line: usize,
+    kind: StatementKind,
+}
+
+impl Statement {
+    fn error(&self, message: String) -> AssemblerError {
+        AssemblerError { file: self.file.clone(), line: self.line, message }
+    }
+}
+
+#[doc = "State shared by the first pass over every included file"]
+struct FirstPass {
+    #[doc = "Wider than an address, a program may end right at the end of memory"]
+    address: u32,
+    symbols: HashMap<String, u16>,
+    statements: Vec<Statement>,
+    include_stack: Vec<PathBuf>,
+}
+
+#[doc = "Assemble a source file, included files are resolved relative to the including file"]
+pub fn assemble_file(path: &Path) -> Result<Vec<u8>, AssemblerError> {
+    let source = read_source(path, path, 0)?;
+
+    assemble(&source, path)
+}
+
+#[doc = "Assemble source text into a rom loaded at 0x200, file_name is used for error messages and includes"]
+pub fn assemble(source: &str, file_name: &Path) -> Result<Vec<u8>, AssemblerError> {
+    let mut first_pass = FirstPass {
+        address: PROGRAM_ADDRESS as u32,
+        symbols: HashMap::new(),
+        statements: Vec::new(),
+        include_stack: vec![file_name.to_path_buf()],
+    };
+
+    parse_source(source, file_name, &mut first_pass)?;
+
+    let mut rom = Vec::new();
+    for statement in first_pass.statements.iter() {
+        encode_statement(statement, &first_pass.symbols, &mut rom)?;
+    }
+
+    Ok(rom)
+}
+
+fn read_source(path: &Path, including_file: &Path, line: usize) -> Result<String, AssemblerError> {
+    std::fs::read_to_string(path).map_err(|error| AssemblerError {
+        file: including_file.to_path_buf(),
+        line,
+        message: format!("can't read {}: {}", path.display(), error),
+    })
+}
+
+#[doc = "First pass: split lines into statements, define labels and constants and follow includes"]
+fn parse_source(source: &str, file_name: &Path, state: &mut FirstPass) -> Result<(), AssemblerError> {
+    for (line_index, raw_line) in source.lines().enumerate() {
+        let line = line_index + 1;
+        let error = |message: String| AssemblerError { file: file_name.to_path_buf(), line, message };
+
+        let mut text = raw_line.split(';').next().unwrap_or("").trim();
+
+        // Any number of labels may precede the statement
+        while let Some(colon) = text.find(':') {
+            let label = text[..colon].trim();
+            if !is_symbol(label) {
+                break;
+            }
+
+            let address = u16::try_from(state.address).map_err(|_| error(format!("'{}' is past the end of memory", label)))?;
+            define_symbol(state, label, address).map_err(error)?;
+            text = text[colon + 1..].trim();
+        }
+
+        if text.is_empty() {
+            continue;
+        }
+
+        let (first_word, rest) = split_word(text);
+
+        if first_word.eq_ignore_ascii_case("include") {
+            let include_name = rest.trim().trim_matches('"');
+            if include_name.is_empty() {
+                return Err(error("include needs a file name".to_string()));
+            }
+
+            let include_path = file_name.parent().unwrap_or(Path::new("")).join(include_name);
+            if state.include_stack.contains(&include_path) {
+                return Err(error(format!("{} includes itself", include_path.display())));
+            }
+
+            let include_source = read_source(&include_path, file_name, line)?;
+            state.include_stack.push(include_path.clone());
+            parse_source(&include_source, &include_path, state)?;
+            state.include_stack.pop();
+            continue;
+        }
+
+        let (second_word, value) = split_word(rest);
+        if second_word.eq_ignore_ascii_case("equ") {
+            if !is_symbol(first_word) {
+                return Err(error(format!("invalid constant name '{}'", first_word)));
+            }
+
+            let value = evaluate(value.trim(), &state.symbols).map_err(error)?;
+            define_symbol(state, first_word, value).map_err(error)?;
+            continue;
+        }
+
+        let operands: Vec<String> = if rest.trim().is_empty() {
+            Vec::new()
+        } else {
+            rest.split(',').map(|operand| operand.trim().to_string()).collect()
+        };
+
+        let mnemonic = first_word.to_ascii_uppercase();
+        let (kind, size) = match mnemonic.as_str() {
+            "DB" => (StatementKind::Bytes(operands.clone()), operands.len()),
+            "DW" => (StatementKind::Words(operands.clone()), operands.len() * 2),
+            _ => {
+                let is_long_load = mnemonic == "LD" && operands.len() == 2 && split_word(&operands[1]).0.eq_ignore_ascii_case("long");
+                let size = if is_long_load { 4 } else { 2 };
+
+                (StatementKind::Instruction { mnemonic, operands }, size)
+            }
+        };
+
+        if state.address as usize + size > 0x10000 {
+            return Err(error("program doesn't fit in memory".to_string()));
+        }
+
+        state.statements.push(Statement { file: file_name.to_path_buf(), line, kind });
+        state.address += size as u32;
+    }
+
+    Ok(())
+}
+
+fn define_symbol(state: &mut FirstPass, name: &str, value: u16) -> Result<(), String> {
+    if state.symbols.insert(name.to_string(), value).is_some() {
+        return Err(format!("'{}' is defined more than once", name));
+    }
+
+    Ok(())
+}
+
+#[doc = "Split off the first whitespace separated word"]
+fn split_word(text: &str) -> (&str, &str) {
+    let text = text.trim_start();
+
+    match text.find(char::is_whitespace) {
+        Some(index) => (&text[..index], &text[index..]),
+        None => (text, ""),
+    }
+}
+
+fn is_symbol(text: &str) -> bool {
+    let mut characters = text.chars();
+
+    match characters.next() {
+        Some(first) if first.is_ascii_alphabetic() || first == '_' => characters.all(|character| character.is_ascii_alphanumeric() || character == '_'),
+        _ => false,
+    }
+}
+
+#[doc = "Value of a number (decimal, 0x / $ hexadecimal, 0b binary) or of a symbol"]
+fn evaluate(operand: &str, symbols: &HashMap<String, u16>) -> Result<u16, String> {
+    let lowercase = operand.to_ascii_lowercase();
+
+    let parsed = if let Some(hex) = lowercase.strip_prefix("0x").or_else(|| lowercase.strip_prefix('$')) {
+        u16::from_str_radix(hex, 16)
+    } else if let Some(binary) = lowercase.strip_prefix("0b") {
+        u16::from_str_radix(binary, 2)
+    } else if lowercase.starts_with(|character: char| character.is_ascii_digit()) {
+        lowercase.parse::<u16>()
+    } else {
+        return symbols.get(operand).copied().ok_or_else(|| format!("undefined symbol '{}'", operand));
+    };
+
+    parsed.map_err(|_| format!("invalid number '{}'", operand))
+}
+
+#[doc = "Register index of a V0 - VF operand"]
+fn register(operand: &str) -> Option<u16> {
+    let mut characters = operand.chars();
+
+    match (characters.next(), characters.next(), characters.next()) {
+        (Some('v' | 'V'), Some(digit), None) => digit.to_digit(16).map(|index| index as u16),
+        _ => None,
+    }
+}
+
+fn encode_statement(statement: &Statement, symbols: &HashMap<String, u16>, rom: &mut Vec<u8>) -> Result<(), AssemblerError> {
+    let value = |operand: &String, maximum: u16| -> Result<u16, AssemblerError> {
+        let value = evaluate(operand, symbols).map_err(|message| statement.error(message))?;
+        if value > maximum {
+            return Err(statement.error(format!("'{}' is out of range, the maximum is 0x{:X}", operand, maximum)));
+        }
+
+        Ok(value)
+    };
+
+    match &statement.kind {
+        StatementKind::Bytes(operands) => {
+            for operand in operands.iter() {
+                rom.push(value(operand, 0xFF)? as u8);
+            }
+        },
+        StatementKind::Words(operands) => {
+            for operand in operands.iter() {
+                rom.extend_from_slice(&value(operand, 0xFFFF)?.to_be_bytes());
+            }
+        },
+        StatementKind::Instruction { mnemonic, operands } => {
+            let words = encode_instruction(mnemonic, operands, &value)
+                .ok_or_else(|| statement.error(format!("invalid instruction '{} {}'", mnemonic, operands.join(", ")).trim_end().to_string()))??;
+
+            for word in words {
+                rom.extend_from_slice(&word.to_be_bytes());
+            }
+        },
+    }
+
+    Ok(())
+}
+
+#[doc = "Encode an instruction, None means the mnemonic / operand combination doesn't exist"]
+fn encode_instruction(mnemonic: &str, operands: &[String], value: &dyn Fn(&String, u16) -> Result<u16, AssemblerError>) -> Option<Result<Vec<u16>, AssemblerError>> {
+    let operand_words: Vec<String> = operands.iter().map(|operand| operand.to_ascii_uppercase()).collect();
+    let words: Vec<&str> = operand_words.iter().map(|operand| operand.as_str()).collect();
+    let registers: Vec<Option<u16>> = operands.iter().map(|operand| register(operand)).collect();
+
+    let single = |opcode: u16| Some(Ok(vec![opcode]));
+    let with_value = |opcode: u16, operand: &String, maximum: u16| Some(value(operand, maximum).map(|value| vec![opcode | value]));
+
+    match (mnemonic, words.as_slice(), registers.as_slice()) {
+        ("CLS", [], _) => single(0x00E0),
+        ("RET", [], _) => single(0x00EE),
+        ("SCR", [], _) => single(0x00FB),
+        ("SCL", [], _) => single(0x00FC),
+        ("EXIT", [], _) => single(0x00FD),
+        ("LOW", [], _) => single(0x00FE),
+        ("HIGH", [], _) => single(0x00FF),
+        ("AUDIO", [], _) => single(0xF002),
+        ("SCD", [_], [None]) => with_value(0x00C0, &operands[0], 0xF),
+        ("SCU", [_], [None]) => with_value(0x00D0, &operands[0], 0xF),
+        ("PLANE", [_], [None]) => Some(value(&operands[0], 0x3).map(|planes| vec![0xF001 | planes << 8])),
+        ("JP", ["V0", _], [_, None]) => with_value(0xB000, &operands[1], 0xFFF),
+        ("JP", [_], [None]) => with_value(0x1000, &operands[0], 0xFFF),
+        ("CALL", [_], [None]) => with_value(0x2000, &operands[0], 0xFFF),
+        ("SE", [_, _], [Some(x), Some(y)]) => single(0x5000 | x << 8 | y << 4),
+        ("SE", [_, _], [Some(x), None]) => with_value(0x3000 | x << 8, &operands[1], 0xFF),
+        ("SNE", [_, _], [Some(x), Some(y)]) => single(0x9000 | x << 8 | y << 4),
+        ("SNE", [_, _], [Some(x), None]) => with_value(0x4000 | x << 8, &operands[1], 0xFF),
+        ("SAVE", [_, _], [Some(x), Some(y)]) => single(0x5002 | x << 8 | y << 4),
+        ("LOAD", [_, _], [Some(x), Some(y)]) => single(0x5003 | x << 8 | y << 4),
+        ("LD", [_, "DT"], [Some(x), _]) => single(0xF007 | x << 8),
+        ("LD", [_, "K"], [Some(x), _]) => single(0xF00A | x << 8),
+        ("LD", [_, "[I]"], [Some(x), _]) => single(0xF065 | x << 8),
+        ("LD", [_, "R"], [Some(x), _]) => single(0xF085 | x << 8),
+        ("LD", ["DT", _], [_, Some(x)]) => single(0xF015 | x << 8),
+        ("LD", ["ST", _], [_, Some(x)]) => single(0xF018 | x << 8),
+        ("LD", ["F", _], [_, Some(x)]) => single(0xF029 | x << 8),
+        ("LD", ["HF", _], [_, Some(x)]) => single(0xF030 | x << 8),
+        ("LD", ["B", _], [_, Some(x)]) => single(0xF033 | x << 8),
+        ("LD", ["[I]", _], [_, Some(x)]) => single(0xF055 | x << 8),
+        ("LD", ["R", _], [_, Some(x)]) => single(0xF075 | x << 8),
+        ("LD", [_, _], [Some(x), Some(y)]) => single(0x8000 | x << 8 | y << 4),
+        ("LD", [_, _], [Some(x), None]) => with_value(0x6000 | x << 8, &operands[1], 0xFF),
+        ("LD", ["I", long], [_, None]) if split_word(long).0 == "LONG" => {
+            let address = split_word(&operands[1]).1.trim().to_string();
+            Some(value(&address, 0xFFFF).map(|address| vec![0xF000, address]))
+        },
+        ("LD", ["I", _], [_, None]) => with_value(0xA000, &operands[1], 0xFFF),
+        ("ADD", ["I", _], [_, Some(x)]) => single(0xF01E | x << 8),
+        ("ADD", [_, _], [Some(x), Some(y)]) => single(0x8004 | x << 8 | y << 4),
+        ("ADD", [_, _], [Some(x), None]) => with_value(0x7000 | x << 8, &operands[1], 0xFF),
+        ("OR", [_, _], [Some(x), Some(y)]) => single(0x8001 | x << 8 | y << 4),
+        ("AND", [_, _], [Some(x), Some(y)]) => single(0x8002 | x << 8 | y << 4),
+        ("XOR", [_, _], [Some(x), Some(y)]) => single(0x8003 | x << 8 | y << 4),
+        ("SUB", [_, _], [Some(x), Some(y)]) => single(0x8005 | x << 8 | y << 4),
+        ("SUBN", [_, _], [Some(x), Some(y)]) => single(0x8007 | x << 8 | y << 4),
+        // Without VY, the shift uses VX as both operands so every shift quirk gives the same result
+        ("SHR", [_], [Some(x)]) => single(0x8006 | x << 8 | x << 4),
+        ("SHR", [_, _], [Some(x), Some(y)]) => single(0x8006 | x << 8 | y << 4),
+        ("SHL", [_], [Some(x)]) => single(0x800E | x << 8 | x << 4),
+        ("SHL", [_, _], [Some(x), Some(y)]) => single(0x800E | x << 8 | y << 4),
+        ("RND", [_, _], [Some(x), None]) => with_value(0xC000 | x << 8, &operands[1], 0xFF),
+        ("DRW", [_, _, _], [Some(x), Some(y), None]) => with_value(0xD000 | x << 8 | y << 4, &operands[2], 0xF),
+        ("SKP", [_], [Some(x)]) => single(0xE09E | x << 8),
+        ("SKNP", [_], [Some(x)]) => single(0xE0A1 | x << 8),
+        ("PITCH", [_], [Some(x)]) => single(0xF03A | x << 8),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disassembler;
+
+    const PROGRAM: &str = "\
+; Every mnemonic once, plus data
+HEIGHT equ 5
+
+start:
+    CLS
+    RET
+    SCR
+    SCL
+    EXIT
+    LOW
+    HIGH
+    AUDIO
+    SCD 4
+    SCU 0xA
+    PLANE 3
+    JP V0, 0x300
+    JP start
+    CALL sub
+    SE V1, V2
+    SE V1, 0x12
+    SNE V3, V4
+    SNE V3, $34
+    SAVE V5, V6
+    LOAD V7, V8
+    LD V9, DT
+    LD VA, K
+    LD VB, [I]
+    LD VC, R
+    LD DT, V1
+    LD ST, V2
+    LD F, V3
+    LD HF, V4
+    LD B, V5
+    LD [I], V6
+    LD R, V7
+    LD V1, V2
+    LD V1, 0b1010
+    LD I, LONG data
+    LD I, data
+    ADD I, V1
+    ADD V1, V2
+    ADD V1, 200
+    OR V1, V2
+    AND V1, V2
+    XOR V1, V2
+    SUB V1, V2
+    SUBN V1, V2
+    SHR V1
+    SHL V1, V2
+    RND V1, 0xFF
+    DRW V1, V2, HEIGHT
+    SKP V1
+    SKNP V2
+    PITCH V3
+    include \"sub.8o\"
+data: db 0xF0, 0x90
+    dw 0xFFFF
+    db 0x42
+";
+
+    const SUBROUTINE: &str = "\
+sub: LD V0, 1
+    RET
+";
+
+    const LISTING: &str = "\
+loc_200:
+0x200: CLS
+0x202: RET
+0x204: SCR
+0x206: SCL
+0x208: EXIT
+0x20A: LOW
+0x20C: HIGH
+0x20E: AUDIO
+0x210: SCD 0x4
+0x212: SCU 0xA
+0x214: PLANE 0x3
+0x216: JP V0, 0x300
+0x218: JP loc_200
+0x21A: CALL sub_266
+0x21C: SE V1, V2
+0x21E: SE V1, 0x12
+0x220: SNE V3, V4
+0x222: SNE V3, 0x34
+0x224: SAVE V5, V6
+0x226: LOAD V7, V8
+0x228: LD V9, DT
+0x22A: LD VA, K
+0x22C: LD VB, [I]
+0x22E: LD VC, R
+0x230: LD DT, V1
+0x232: LD ST, V2
+0x234: LD F, V3
+0x236: LD HF, V4
+0x238: LD B, V5
+0x23A: LD [I], V6
+0x23C: LD R, V7
+0x23E: LD V1, V2
+0x240: LD V1, 0x0A
+0x242: LD I, LONG 0x026A
+0x246: LD I, 0x26A
+0x248: ADD I, V1
+0x24A: ADD V1, V2
+0x24C: ADD V1, 0xC8
+0x24E: OR V1, V2
+0x250: AND V1, V2
+0x252: XOR V1, V2
+0x254: SUB V1, V2
+0x256: SUBN V1, V2
+0x258: SHR V1, V1
+0x25A: SHL V1, V2
+0x25C: RND V1, 0xFF
+0x25E: DRW V1, V2, 0x5
+0x260: SKP V1
+0x262: SKNP V2
+0x264: PITCH V3
+sub_266:
+0x266: LD V0, 0x01
+0x268: RET
+0x26A: DW 0xF090
+0x26C: DW 0xFFFF
+0x26E: DB 0x42
+";
+
+    #[doc = "Directory of source files for a test, removed with remove_dir_all once it is done"]
+    fn source_directory(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("nauka-assembler-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+
+        for (file, text) in files {
+            std::fs::write(directory.join(file), text).unwrap();
+        }
+
+        directory
+    }
+
+    fn assemble_error(source: &str) -> AssemblerError {
+        assemble(source, Path::new("test.8o")).expect_err("the source should not assemble")
+    }
+
+    #[test]
+    fn round_trip() {
+        let directory = source_directory("round-trip", &[("main.8o", PROGRAM), ("sub.8o", SUBROUTINE)]);
+        let rom = assemble_file(&directory.join("main.8o"));
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(disassembler::disassemble(&rom.unwrap(), PROGRAM_ADDRESS).to_string(), LISTING);
+    }
+
+    #[test]
+    fn undefined_symbol() {
+        let error = assemble_error("CLS\n\nJP nowhere\n");
+
+        assert_eq!(error.file, Path::new("test.8o"));
+        assert_eq!(error.line, 3);
+        assert_eq!(error.message, "undefined symbol 'nowhere'");
+    }
+
+    #[test]
+    fn values_out_of_range() {
+        let error = assemble_error("LD V1, 256\n");
+        assert_eq!((error.line, error.message.as_str()), (1, "'256' is out of range, the maximum is 0xFF"));
+
+        let error = assemble_error("CLS\nDRW V1, V2, 16\n");
+        assert_eq!((error.line, error.message.as_str()), (2, "'16' is out of range, the maximum is 0xF"));
+
+        let error = assemble_error("TOO_FAR equ 0x1000\nCLS\nJP TOO_FAR\n");
+        assert_eq!((error.line, error.message.as_str()), (3, "'TOO_FAR' is out of range, the maximum is 0xFFF"));
+
+        let error = assemble_error("db 1, 0x100\n");
+        assert_eq!((error.line, error.message.as_str()), (1, "'0x100' is out of range, the maximum is 0xFF"));
+
+        let error = assemble_error("dw 65536\n");
+        assert_eq!((error.line, error.message.as_str()), (1, "invalid number '65536'"));
+    }
+
+    #[test]
+    fn recursive_includes() {
+        let directory = source_directory("recursive", &[
+            ("main.8o", "CLS\ninclude \"a.8o\"\n"),
+            ("a.8o", "RET\n\ninclude \"main.8o\"\n"),
+            ("self.8o", "include \"self.8o\"\n"),
+        ]);
+        let error = assemble_file(&directory.join("main.8o")).unwrap_err();
+        let self_error = assemble_file(&directory.join("self.8o")).unwrap_err();
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(error.file, directory.join("a.8o"));
+        assert_eq!(error.line, 3);
+        assert_eq!(error.message, format!("{} includes itself", directory.join("main.8o").display()));
+
+        assert_eq!(self_error.file, directory.join("self.8o"));
+        assert_eq!(self_error.line, 1);
+    }
+
+    #[test]
+    fn program_filling_memory() {
+        // 0x200 to 0xFFFF holds 0x7F00 two byte instructions, the last one at 0xFFFE
+        let source = "CLS\n".repeat(0x7F00);
+
+        let rom = assemble(&source, Path::new("test.8o")).unwrap();
+        assert_eq!(rom.len(), 0x10000 - PROGRAM_ADDRESS as usize);
+        assert_eq!(&rom[rom.len() - 2..], [0x00, 0xE0]);
+
+        let error = assemble_error(&format!("{}db 0", source));
+        assert_eq!((error.line, error.message.as_str()), (0x7F01, "program doesn't fit in memory"));
+
+        let error = assemble_error(&format!("{}end:", source));
+        assert_eq!((error.line, error.message.as_str()), (0x7F01, "'end' is past the end of memory"));
+    }
+
+    #[test]
+    fn missing_include() {
+        let error = assemble_error("CLS\ninclude \"missing.8o\"\n");
+
+        assert_eq!(error.line, 2);
+        assert!(error.message.starts_with("can't read missing.8o"), "{}", error.message);
+    }
+}
@@ -0,0 +1,238 @@
+use std::str::FromStr;
+
+use crate::emulator::Emulator;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Waveform {
+    Square,
+    Sine,
+}
+
+impl FromStr for Waveform {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "square" => Ok(Waveform::Square),
+            "sine" => Ok(Waveform::Sine),
+            _ => Err(format!("unknown waveform '{}', expected 'square' or 'sine'", value)),
+        }
+    }
+}
+
+#[doc = "Destination of the generated beeper samples, e.g. an audio device or a capture buffer"]
+pub trait AudioSink {
+    fn sample_rate(&self) -> u32;
+
+    fn queue_samples(&mut self, samples: &[f32]);
+}
+
+#[doc = "Audio sink that keeps every sample in memory, useful for headless runs"]
+#[derive(Debug, Clone)]
+pub struct CaptureSink {
+    sample_rate: u32,
+    samples: Vec<f32>,
+}
+
+impl CaptureSink {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate,
+            samples: Vec::new(),
+        }
+    }
+
+    pub fn samples(&self) -> &[f32] {
+        &self.samples
+    }
+}
+
+impl AudioSink for CaptureSink {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn queue_samples(&mut self, samples: &[f32]) {
+        self.samples.extend_from_slice(samples);
+    }
+}
+
+#[doc = "Mono tone generator producing samples in the -volume..volume range"]
+#[derive(Debug, Clone)]
+pub struct ToneGenerator {
+    waveform: Waveform,
+    frequency: f32,
+    volume: f32,
+    sample_rate: u32,
+    // Position inside the current period, in the 0..1 range
+    phase: f32,
+    // Position inside the XO-CHIP audio pattern, in bits
+    pattern_position: f32,
+}
+
+impl ToneGenerator {
+    pub fn new(waveform: Waveform, frequency: f32, volume: f32, sample_rate: u32) -> Self {
+        Self {
+            waveform,
+            frequency,
+            volume: volume.clamp(0.0, 1.0),
+            sample_rate,
+            phase: 0.0,
+            pattern_position: 0.0,
+        }
+    }
+
+    #[doc = "Fill the buffer with the tone, or with silence if the tone isn't active"]
+    pub fn fill(&mut self, active: bool, samples: &mut [f32]) {
+        if !active {
+            self.phase = 0.0;
+            samples.fill(0.0);
+            return;
+        }
+
+        let phase_step = self.frequency / self.sample_rate as f32;
+
+        for sample in samples.iter_mut() {
+            *sample = match self.waveform {
+                Waveform::Square => if self.phase < 0.5 { self.volume } else { -self.volume },
+                Waveform::Sine => (self.phase * std::f32::consts::TAU).sin() * self.volume,
+            };
+
+            self.phase = (self.phase + phase_step).fract();
+        }
+    }
+
+    #[doc = "Fill the buffer with a looping 128 bit XO-CHIP pattern played at the given bits per second"]
+    pub fn fill_pattern(&mut self, active: bool, pattern: &[u8; 16], playback_rate: f32, samples: &mut [f32]) {
+        if !active {
+            self.pattern_position = 0.0;
+            samples.fill(0.0);
+            return;
+        }
+
+        let bit_step = playback_rate / self.sample_rate as f32;
+
+        for sample in samples.iter_mut() {
+            let bit = self.pattern_position as usize;
+            let bit_set = pattern[bit / 8] & (0x80 >> (bit % 8)) != 0;
+
+            *sample = if bit_set { self.volume } else { -self.volume };
+
+            self.pattern_position = (self.pattern_position + bit_step) % 128.0;
+        }
+    }
+}
+
+#[doc = "Drives an audio sink from the emulator's sound timer, one 60 Hz frame at a time"]
+pub struct Beeper<S: AudioSink> {
+    generator: ToneGenerator,
+    sink: S,
+    buffer: Vec<f32>,
+}
+
+impl<S: AudioSink> Beeper<S> {
+    pub fn new(waveform: Waveform, frequency: f32, volume: f32, frames_per_second: u32, sink: S) -> Self {
+        let sample_rate = sink.sample_rate();
+
+        Self {
+            generator: ToneGenerator::new(waveform, frequency, volume, sample_rate),
+            sink,
+            buffer: vec![0.0; (sample_rate / frames_per_second) as usize],
+        }
+    }
+
+    #[doc = "Generate the samples of one frame and hand them to the sink"]
+    pub fn update(&mut self, emulator: &Emulator) {
+        match emulator.audio_pattern() {
+            Some(pattern) => self.generator.fill_pattern(emulator.sound_active(), pattern, emulator.audio_playback_rate(), &mut self.buffer),
+            None => self.generator.fill(emulator.sound_active(), &mut self.buffer),
+        }
+        self.sink.queue_samples(&self.buffer);
+    }
+
+    pub fn sink(&self) -> &S {
+        &self.sink
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Instruction, Quirks, Variant};
+
+    const SAMPLE_RATE: u32 = 48000;
+
+    #[doc = "Samples of one frame at 60 fps, with the sound timer set to the given value"]
+    fn frame(emulator: &mut Emulator, sound_timer: u8) -> Vec<f32> {
+        emulator.set_register(0, sound_timer);
+        emulator.execute(&Instruction::LdStVx { x: 0 }).unwrap();
+
+        let mut beeper = Beeper::new(Waveform::Square, 1000.0, 0.5, 60, CaptureSink::new(SAMPLE_RATE));
+        beeper.update(emulator);
+        beeper.sink().samples().to_vec()
+    }
+
+    #[doc = "Whether every run of equal samples but the last, cut by the end of the frame, lasts about the given length"]
+    fn runs_last(samples: &[f32], length: usize) -> bool {
+        let runs: Vec<usize> = samples.chunk_by(|a, b| a == b).map(<[f32]>::len).collect();
+
+        // The phase accumulates rounding errors of up to a sample
+        runs.len() > 1 && runs[..runs.len() - 1].iter().all(|run| run.abs_diff(length) <= 1)
+    }
+
+    #[test]
+    fn silence_without_sound_timer() {
+        let mut emulator = Emulator::new(Vec::new(), Variant::Chip8, Quirks::default()).unwrap();
+
+        let samples = frame(&mut emulator, 0);
+        assert_eq!(samples.len(), 800);
+        assert!(samples.iter().all(|sample| *sample == 0.0));
+    }
+
+    #[test]
+    fn square_tone() {
+        let mut emulator = Emulator::new(Vec::new(), Variant::Chip8, Quirks::default()).unwrap();
+
+        // 1000 Hz at 48 kHz is 24 samples high then 24 low
+        let samples = frame(&mut emulator, 2);
+        assert_eq!(samples.len(), 800);
+        assert_eq!(samples[0], 0.5);
+        assert_eq!(samples[24], -0.5);
+        assert!(runs_last(&samples, 24));
+    }
+
+    #[test]
+    fn xo_chip_pattern() {
+        let mut emulator = Emulator::new(Vec::new(), Variant::XoChip, Quirks::default()).unwrap();
+        emulator.write_memory(0x300, &[0xF0; 16]).unwrap();
+        emulator.set_i(0x300);
+        emulator.execute(&Instruction::Audio).unwrap();
+
+        assert!(frame(&mut emulator, 0).iter().all(|sample| *sample == 0.0));
+
+        // 4 bits set then 4 cleared, each bit lasts 12 samples at 4000 bits per second
+        let samples = frame(&mut emulator, 2);
+        assert_eq!(samples[..2], [0.5, 0.5]);
+        assert!(runs_last(&samples, 48));
+
+        // A pitch of 112 doubles the playback rate
+        emulator.set_register(1, 112);
+        emulator.execute(&Instruction::PitchVx { x: 1 }).unwrap();
+        let samples = frame(&mut emulator, 2);
+        assert_eq!(samples[0], 0.5);
+        assert!(runs_last(&samples, 24));
+    }
+
+    #[test]
+    fn pattern_loops_over_128_bits() {
+        let mut pattern = [0; 16];
+        pattern[0] = 0x80;
+        let mut generator = ToneGenerator::new(Waveform::Square, 440.0, 1.0, 128);
+
+        // One bit per sample, the set bit comes back every 128 samples
+        let mut samples = [0.0; 300];
+        generator.fill_pattern(true, &pattern, 128.0, &mut samples);
+        let high: Vec<usize> = (0..samples.len()).filter(|index| samples[*index] == 1.0).collect();
+        assert_eq!(high, [0, 128, 256]);
+    }
+}
@@ -0,0 +1,48 @@
+use nauka::audio::AudioSink;
+use sdl2::{audio::{AudioQueue, AudioSpecDesired}, AudioSubsystem};
+
+#[doc = "Number of frames worth of samples allowed to wait in the queue before new ones are dropped"]
+const MAX_QUEUED_FRAMES: u32 = 4;
+
+pub struct SdlAudioSink {
+    queue: AudioQueue<f32>,
+    max_queued_bytes: u32,
+}
+
+impl SdlAudioSink {
+    pub fn new(audio: &AudioSubsystem, sample_rate: i32, frames_per_second: u32) -> Result<Self, String> {
+        let desired_spec = AudioSpecDesired {
+            freq: Some(sample_rate),
+            channels: Some(1),
+            samples: None,
+        };
+
+        let queue = audio.open_queue::<f32, _>(None, &desired_spec)?;
+        queue.resume();
+
+        let samples_per_frame = queue.spec().freq as u32 / frames_per_second;
+
+        Ok(Self {
+            queue,
+            max_queued_bytes: samples_per_frame * MAX_QUEUED_FRAMES * std::mem::size_of::<f32>() as u32,
+        })
+    }
+}
+
+impl AudioSink for SdlAudioSink {
+    fn sample_rate(&self) -> u32 {
+        self.queue.spec().freq as u32
+    }
+
+    fn queue_samples(&mut self, samples: &[f32]) {
+        // Keep the latency bounded if the device consumes slower than we produce
+        if self.queue.size() > self.max_queued_bytes {
+            return;
+        }
+
+        // Dropping the samples is a glitch at worst, not worth stopping the emulator for
+        if let Err(error) = self.queue.queue_audio(samples) {
+            eprintln!("Failed to queue audio samples: {}", error);
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+
+use sdl2::controller::{Button, GameController};
+use sdl2::event::Event;
+use sdl2::joystick::Joystick;
+use sdl2::{GameControllerSubsystem, JoystickSubsystem, Sdl};
+
+#[doc = "Joystick hat directions as D-pad buttons, by hat state bit"]
+const HAT_BUTTONS: [(u8, Button); 4] = [
+    (0x1, Button::DPadUp),
+    (0x2, Button::DPadRight),
+    (0x4, Button::DPadDown),
+    (0x8, Button::DPadLeft)
+];
+
+#[doc = "Joystick buttons used as the face buttons, by button index"]
+const JOYSTICK_BUTTONS: [Button; 4] = [Button::A, Button::B, Button::X, Button::Y];
+
+#[doc = "Connected game controllers, plus the joysticks SDL has no controller mapping for"]
+pub struct Controllers {
+    controller_subsystem: GameControllerSubsystem,
+    joystick_subsystem: JoystickSubsystem,
+    controllers: Vec<GameController>,
+    joysticks: Vec<Joystick>,
+    #[doc = "Last state of each joystick hat, by joystick instance id and hat index"]
+    hats: HashMap<(u32, u8), u8>,
+    #[doc = "Buttons held on each device, by instance id"]
+    held: HashMap<u32, Vec<Button>>,
+}
+
+impl Controllers {
+    #[doc = "Devices plugged in at startup are opened by the added events SDL sends first"]
+    pub fn new(sdl: &Sdl) -> Result<Self, String> {
+        Ok(Self {
+            controller_subsystem: sdl.game_controller()?,
+            joystick_subsystem: sdl.joystick()?,
+            controllers: Vec::new(),
+            joysticks: Vec::new(),
+            hats: HashMap::new(),
+            held: HashMap::new(),
+        })
+    }
+
+    #[doc = "Open and close devices as they are plugged in and out, and turn their events into button presses (true) and releases"]
+    #[doc = ""]
+    #[doc = "A button stays pressed while any device holds it, unplugging a device releases the buttons it held"]
+    pub fn handle_event(&mut self, event: &Event) -> Vec<(Button, bool)> {
+        let (which, changes) = match *event {
+            Event::ControllerDeviceAdded { which, .. } => {
+                match self.controller_subsystem.open(which) {
+                    Ok(controller) => {
+                        println!("Controller connected: {}", controller.name());
+                        self.controllers.push(controller);
+                    },
+                    Err(error) => eprintln!("Failed to open a controller: {}", error),
+                }
+
+                return Vec::new();
+            },
+            Event::ControllerDeviceRemoved { which, .. } => {
+                self.controllers.retain(|controller| controller.instance_id() != which);
+                return self.release_device(which);
+            },
+            Event::ControllerButtonDown { which, button, .. } => (which, vec![(button, true)]),
+            Event::ControllerButtonUp { which, button, .. } => (which, vec![(button, false)]),
+            // Controllers also send joystick events, they are opened as controllers
+            Event::JoyDeviceAdded { which, .. } if !self.controller_subsystem.is_game_controller(which) => {
+                match self.joystick_subsystem.open(which) {
+                    Ok(joystick) => {
+                        println!("Joystick connected: {}", joystick.name());
+                        self.joysticks.push(joystick);
+                    },
+                    Err(error) => eprintln!("Failed to open a joystick: {}", error),
+                }
+
+                return Vec::new();
+            },
+            Event::JoyDeviceRemoved { which, .. } if self.is_joystick(which) => {
+                self.joysticks.retain(|joystick| joystick.instance_id() != which);
+                self.hats.retain(|(joystick, _), _| *joystick != which);
+                return self.release_device(which);
+            },
+            Event::JoyButtonDown { which, button_idx, .. } if self.is_joystick(which) => {
+                (which, JOYSTICK_BUTTONS.get(button_idx as usize).map(|button| vec![(*button, true)]).unwrap_or_default())
+            },
+            Event::JoyButtonUp { which, button_idx, .. } if self.is_joystick(which) => {
+                (which, JOYSTICK_BUTTONS.get(button_idx as usize).map(|button| vec![(*button, false)]).unwrap_or_default())
+            },
+            Event::JoyHatMotion { which, hat_idx, state, .. } if self.is_joystick(which) => {
+                let state = state.to_raw();
+                let previous = self.hats.insert((which, hat_idx), state).unwrap_or(0);
+
+                let changes = HAT_BUTTONS
+                    .iter()
+                    .filter(|(bit, _)| (state ^ previous) & bit != 0)
+                    .map(|(bit, button)| (*button, state & bit != 0))
+                    .collect();
+
+                (which, changes)
+            },
+            _ => return Vec::new(),
+        };
+
+        let mut reported = Vec::new();
+        for (button, pressed) in changes {
+            let held = self.held.entry(which).or_default();
+            held.retain(|held| *held != button);
+            if pressed {
+                held.push(button);
+            }
+
+            // Releases are only reported once no other device holds the button
+            if pressed || !self.is_held(button) {
+                reported.push((button, pressed));
+            }
+        }
+
+        reported
+    }
+
+    #[doc = "Forget the buttons a device held, returning the releases of those no other device holds"]
+    fn release_device(&mut self, instance_id: u32) -> Vec<(Button, bool)> {
+        let held = self.held.remove(&instance_id).unwrap_or_default();
+
+        held.into_iter().filter(|button| !self.is_held(*button)).map(|button| (button, false)).collect()
+    }
+
+    fn is_held(&self, button: Button) -> bool {
+        self.held.values().any(|held| held.contains(&button))
+    }
+
+    fn is_joystick(&self, instance_id: u32) -> bool {
+        self.joysticks.iter().any(|joystick| joystick.instance_id() == instance_id)
+    }
+}
@@ -0,0 +1,254 @@
+use std::io::{BufRead, Write};
+
+use nauka::debugger::{DebugStop, Debugger, OpcodePattern, Watchpoint};
+use nauka::{disassembler, screen, Emulator};
+
+use crate::AppConfiguration;
+
+#[doc = "Instructions a continue runs at most before giving the prompt back"]
+const RUN_LIMIT: u32 = 10_000_000;
+
+const HELP: &str = "\
+step [n]            execute n instructions (s)
+next                step over a CALL (n)
+finish              run until the current subroutine returns (f)
+continue            run until a breakpoint (c)
+break <addr>        add a breakpoint (b)
+break-op <opcode>   add an opcode breakpoint, e.g. DXYN or F?55 (bo)
+delete <addr|op>    remove a breakpoint (d)
+watch <a>[-<b>] [r|w|rw]  stop when memory is read and/or written, writes by default (w)
+watch V<x> [== <v>] stop when a register changes or takes a value
+watch I <a>[-<b>]   stop when I moves into a range
+unwatch <n>         remove the watchpoint with the number shown by list
+list                list the breakpoints and watchpoints (l)
+regs                show the registers and timers (r)
+stack               show the return addresses
+mem <addr> [len]    dump memory (x)
+dis [addr] [n]      disassemble n instructions
+screen              print the screen
+key <key> [up]      press a keypad key, it stays held until released with up
+quit                exit (q)";
+
+#[doc = "Run a rom under the debugger, driven by commands read from stdin"]
+pub fn run(configuration: AppConfiguration) {
+    let mut emulator = crate::create_emulator(&configuration, configuration.variant, configuration.quirks.quirks());
+
+    if let Some(seed) = configuration.seed {
+        emulator.set_rng_seed(seed);
+    }
+
+    emulator.set_tracer(crate::create_tracer(&configuration));
+
+    let mut debugger = Debugger::new(configuration.ipf);
+
+    println!("Type help for the list of commands");
+    print_current_instruction(&emulator);
+
+    let stdin = std::io::stdin();
+    loop {
+        print!("(nauka) ");
+        std::io::stdout().flush().expect("Failed to write to stdout!");
+
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).expect("Failed to read from stdin!") == 0 {
+            break;
+        }
+
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let Some((command, arguments)) = words.split_first() else {
+            continue;
+        };
+
+        match execute_command(command, arguments, &mut emulator, &mut debugger) {
+            Ok(true) => {},
+            Ok(false) => break,
+            Err(error) => println!("error: {}", error),
+        }
+    }
+}
+
+#[doc = "Returns Ok(false) once the debugger should exit"]
+fn execute_command(command: &str, arguments: &[&str], emulator: &mut Emulator, debugger: &mut Debugger) -> Result<bool, String> {
+    match command {
+        "s" | "step" => {
+            let count = match arguments.first() {
+                Some(count) => count.parse().map_err(|_| format!("invalid count '{}'", count))?,
+                None => 1,
+            };
+            report_stop(debugger.step(emulator, count), emulator);
+        },
+        "n" | "next" => report_stop(debugger.step_over(emulator, RUN_LIMIT), emulator),
+        "f" | "finish" => report_stop(debugger.run_to_return(emulator, RUN_LIMIT), emulator),
+        "c" | "continue" => report_stop(debugger.resume(emulator, RUN_LIMIT), emulator),
+        "b" | "break" => {
+            let address = parse_address(argument(arguments, 0)?)?;
+            debugger.add_breakpoint(address);
+            println!("Breakpoint at 0x{:03X}", address);
+        },
+        "bo" | "break-op" => {
+            let pattern: OpcodePattern = argument(arguments, 0)?.parse()?;
+            debugger.add_opcode_breakpoint(pattern);
+            println!("Breakpoint on opcode {}", pattern);
+        },
+        "d" | "delete" => {
+            let target = argument(arguments, 0)?;
+            let removed = match target.parse::<OpcodePattern>() {
+                Ok(pattern) if target.len() == 4 && debugger.remove_opcode_breakpoint(pattern) => true,
+                _ => debugger.remove_breakpoint(parse_address(target)?),
+            };
+
+            if !removed {
+                return Err(format!("no breakpoint at {}", target));
+            }
+        },
+        "l" | "list" => {
+            debugger.breakpoints().for_each(|address| println!("0x{:03X}", address));
+            debugger.opcode_breakpoints().iter().for_each(|pattern| println!("opcode {}", pattern));
+            debugger.watchpoints().iter().enumerate().for_each(|(index, watchpoint)| println!("watch #{} {}", index, watchpoint));
+        },
+        "w" | "watch" => {
+            let watchpoint = parse_watchpoint(arguments)?;
+            debugger.add_watchpoint(watchpoint);
+            println!("Watchpoint {}", watchpoint);
+        },
+        "unwatch" => {
+            let index = argument(arguments, 0)?;
+            let index = index.trim_start_matches('#').parse().map_err(|_| format!("invalid watchpoint number '{}'", index))?;
+
+            if debugger.remove_watchpoint(index).is_none() {
+                return Err(format!("no watchpoint #{}", index));
+            }
+        },
+        "r" | "regs" => print_registers(emulator),
+        "stack" => {
+            for (depth, address) in emulator.stack().iter().enumerate().rev() {
+                println!("#{} 0x{:03X}", depth, address);
+            }
+        },
+        "x" | "mem" => {
+            let address = parse_address(argument(arguments, 0)?)? as usize;
+            let length = match arguments.get(1) {
+                Some(length) => length.parse().map_err(|_| format!("invalid length '{}'", length))?,
+                None => 16,
+            };
+            let memory = emulator.memory();
+            let end = address.saturating_add(length).min(memory.len());
+
+            for (row, bytes) in memory[address.min(end)..end].chunks(16).enumerate() {
+                let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+                println!("0x{:03X}: {}", address + row * 16, hex.join(" "));
+            }
+        },
+        "dis" => {
+            let mut address = match arguments.first() {
+                Some(address) => parse_address(address)? as usize,
+                None => emulator.pc() as usize,
+            };
+            let count = match arguments.get(1) {
+                Some(count) => count.parse().map_err(|_| format!("invalid count '{}'", count))?,
+                None => 8,
+            };
+
+            for _ in 0..count {
+                let Some(memory) = emulator.memory().get(address..) else {
+                    break;
+                };
+                let (text, length) = disassembler::disassemble_instruction(memory);
+                if length == 0 {
+                    break;
+                }
+
+                println!("0x{:03X}: {}", address, text);
+                address += length;
+            }
+        },
+        "screen" => print!("{}", screen::to_ascii(emulator)),
+        "key" => {
+            let key = u8::from_str_radix(argument(arguments, 0)?, 16).ok().filter(|key| *key < 16).ok_or_else(|| "keys go from 0 to F".to_string())?;
+            let mut keypad = emulator.keypad();
+
+            match arguments.get(1).copied() {
+                None => keypad.press(key),
+                Some("up") => keypad.release(key),
+                Some(action) => return Err(format!("invalid action '{}', expected up", action)),
+            }
+            emulator.set_keypad(keypad);
+        },
+        "h" | "help" => println!("{}", HELP),
+        "q" | "quit" => return Ok(false),
+        _ => return Err(format!("unknown command '{}', type help for the list of commands", command)),
+    }
+
+    Ok(true)
+}
+
+fn argument<'a>(arguments: &[&'a str], index: usize) -> Result<&'a str, String> {
+    arguments.get(index).copied().ok_or_else(|| "missing argument".to_string())
+}
+
+#[doc = "Addresses are hexadecimal, with or without 0x"]
+fn parse_address(text: &str) -> Result<u16, String> {
+    u16::from_str_radix(text.trim_start_matches("0x"), 16).map_err(|_| format!("invalid address '{}'", text))
+}
+
+fn parse_watchpoint(arguments: &[&str]) -> Result<Watchpoint, String> {
+    let target = argument(arguments, 0)?;
+
+    if target.eq_ignore_ascii_case("i") {
+        let (start, end) = parse_range(argument(arguments, 1)?)?;
+        return Ok(Watchpoint::IInRange { start, end });
+    }
+
+    if let Some(register) = target.strip_prefix(['v', 'V']) {
+        let x = u8::from_str_radix(register, 16).ok().filter(|x| *x < 16).ok_or_else(|| format!("invalid register '{}'", target))?;
+
+        return match arguments.get(1..) {
+            Some(["==", value]) => Ok(Watchpoint::RegisterEquals(x, u8::from_str_radix(value.trim_start_matches("0x"), 16).map_err(|_| format!("invalid value '{}'", value))?)),
+            Some([]) => Ok(Watchpoint::RegisterChanged(x)),
+            _ => Err("expected watch V<x> or watch V<x> == <value>".to_string()),
+        };
+    }
+
+    let (start, end) = parse_range(target)?;
+    let (read, write) = match arguments.get(1).copied() {
+        None | Some("w") => (false, true),
+        Some("r") => (true, false),
+        Some("rw") => (true, true),
+        Some(access) => return Err(format!("invalid access '{}', expected r, w or rw", access)),
+    };
+
+    Ok(Watchpoint::Memory { start, end, read, write })
+}
+
+#[doc = "A single address or an inclusive start-end range"]
+fn parse_range(text: &str) -> Result<(u16, u16), String> {
+    match text.split_once('-') {
+        Some((start, end)) => Ok((parse_address(start)?, parse_address(end)?)),
+        None => parse_address(text).map(|address| (address, address)),
+    }
+}
+
+fn report_stop(stop: DebugStop, emulator: &Emulator) {
+    if stop != DebugStop::Done {
+        println!("{}", stop);
+    }
+
+    print_current_instruction(emulator);
+}
+
+fn print_current_instruction(emulator: &Emulator) {
+    let pc = emulator.pc() as usize;
+
+    match emulator.memory().get(pc..) {
+        Some(memory) if !memory.is_empty() => println!("0x{:03X}: {}", pc, disassembler::disassemble_instruction(memory).0),
+        _ => println!("0x{:03X}: out of memory", pc),
+    }
+}
+
+fn print_registers(emulator: &Emulator) {
+    let registers: Vec<String> = emulator.registers().iter().enumerate().map(|(x, value)| format!("V{:X}={:02X}", x, value)).collect();
+
+    println!("{}", registers[..8].join(" "));
+    println!("{}", registers[8..].join(" "));
+    println!("PC={:03X} I={:03X} SP={:X} DT={:02X} ST={:02X}", emulator.pc(), emulator.i(), emulator.sp(), emulator.delay_timer(), emulator.sound_timer());
+}
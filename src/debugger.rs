@@ -0,0 +1,444 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::emulator::{Emulator, MemoryAccessKind};
+use crate::error::{EmulatorError, StepOutcome};
+use crate::instruction::Instruction;
+
+#[doc = "Opcode with wildcard nibbles, parsed from 4 characters where anything but a hex digit matches any nibble"]
+#[doc = ""]
+#[doc = "For example `DXYN` matches every draw and `F?55` every LD [I], Vx"]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpcodePattern {
+    value: u16,
+    mask: u16,
+}
+
+impl OpcodePattern {
+    pub fn matches(&self, opcode: u16) -> bool {
+        opcode & self.mask == self.value
+    }
+}
+
+impl FromStr for OpcodePattern {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let text = value.trim_start_matches("0x");
+        if text.chars().count() != 4 {
+            return Err(format!("'{}' isn't a 4 digit opcode", value));
+        }
+
+        let (value, mask) = text.chars().fold((0, 0), |(value, mask), character| {
+            match character.to_digit(16) {
+                Some(digit) => ((value << 4) | digit as u16, (mask << 4) | 0xF),
+                None => (value << 4, mask << 4),
+            }
+        });
+
+        Ok(Self { value, mask })
+    }
+}
+
+impl fmt::Display for OpcodePattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for shift in [12, 8, 4, 0] {
+            if (self.mask >> shift) & 0xF == 0 {
+                write!(f, "?")?;
+            } else {
+                write!(f, "{:X}", (self.value >> shift) & 0xF)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[doc = "Condition checked after every instruction, ranges include both ends"]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Watchpoint {
+    #[doc = "Memory in the range is read or written, depending on the flags"]
+    Memory { start: u16, end: u16, read: bool, write: bool },
+    #[doc = "The register value changes"]
+    RegisterChanged(u8),
+    #[doc = "The register takes the value, having a different one before"]
+    RegisterEquals(u8, u8),
+    #[doc = "I moves into the range"]
+    IInRange { start: u16, end: u16 },
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Watchpoint::Memory { start, end, read, write } => {
+                let access = match (read, write) {
+                    (true, true) => "access",
+                    (true, false) => "read",
+                    _ => "write",
+                };
+                write!(f, "{} 0x{:03X}-0x{:03X}", access, start, end)
+            },
+            Watchpoint::RegisterChanged(x) => write!(f, "V{:X} changes", x),
+            Watchpoint::RegisterEquals(x, value) => write!(f, "V{:X} == 0x{:02X}", x, value),
+            Watchpoint::IInRange { start, end } => write!(f, "I in 0x{:03X}-0x{:03X}", start, end),
+        }
+    }
+}
+
+#[doc = "Why the debugger gave control back"]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DebugStop {
+    #[doc = "The requested instructions ran"]
+    Done,
+    Breakpoint(u16),
+    OpcodeBreakpoint { pc: u16, opcode: u16 },
+    #[doc = "The instruction at pc triggered the watchpoint"]
+    Watchpoint { watchpoint: Watchpoint, pc: u16 },
+    #[doc = "LD Vx, K is waiting for a key, running further would spin"]
+    WaitingForKey,
+    #[doc = "The program jumps to itself at the given address and would never hit a breakpoint"]
+    SelfLoop(u16),
+    Exit,
+    Crashed(EmulatorError),
+    #[doc = "The instruction limit of a run was reached"]
+    Limit,
+}
+
+impl fmt::Display for DebugStop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DebugStop::Done => write!(f, "stopped"),
+            DebugStop::Breakpoint(address) => write!(f, "breakpoint at 0x{:03X}", address),
+            DebugStop::OpcodeBreakpoint { pc, opcode } => write!(f, "opcode breakpoint on 0x{:04X} at 0x{:03X}", opcode, pc),
+            DebugStop::Watchpoint { watchpoint, pc } => write!(f, "watchpoint {} hit by 0x{:03X}", watchpoint, pc),
+            DebugStop::WaitingForKey => write!(f, "waiting for a key"),
+            DebugStop::SelfLoop(address) => write!(f, "program loops on itself at 0x{:03X}", address),
+            DebugStop::Exit => write!(f, "program exited"),
+            DebugStop::Crashed(error) => write!(f, "crashed: {}", error),
+            DebugStop::Limit => write!(f, "instruction limit reached"),
+        }
+    }
+}
+
+#[doc = "Breakpoints and stepping on top of an Emulator, timers tick every instructions_per_frame instructions"]
+#[derive(Debug, Clone)]
+pub struct Debugger {
+    breakpoints: BTreeSet<u16>,
+    opcode_breakpoints: Vec<OpcodePattern>,
+    watchpoints: Vec<Watchpoint>,
+    instructions_per_frame: u32,
+    #[doc = "Instructions executed in the current frame"]
+    frame_cycles: u32,
+}
+
+impl Debugger {
+    pub fn new(instructions_per_frame: u32) -> Self {
+        Self {
+            breakpoints: BTreeSet::new(),
+            opcode_breakpoints: Vec::new(),
+            watchpoints: Vec::new(),
+            instructions_per_frame: instructions_per_frame.max(1),
+            frame_cycles: 0,
+        }
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = &u16> {
+        self.breakpoints.iter()
+    }
+
+    pub fn opcode_breakpoints(&self) -> &[OpcodePattern] {
+        &self.opcode_breakpoints
+    }
+
+    pub fn add_breakpoint(&mut self, address: u16) {
+        self.breakpoints.insert(address);
+    }
+
+    #[doc = "Returns whether there was a breakpoint at the address"]
+    pub fn remove_breakpoint(&mut self, address: u16) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    pub fn add_opcode_breakpoint(&mut self, pattern: OpcodePattern) {
+        if !self.opcode_breakpoints.contains(&pattern) {
+            self.opcode_breakpoints.push(pattern);
+        }
+    }
+
+    #[doc = "Returns whether the pattern was a breakpoint"]
+    pub fn remove_opcode_breakpoint(&mut self, pattern: OpcodePattern) -> bool {
+        let length = self.opcode_breakpoints.len();
+        self.opcode_breakpoints.retain(|breakpoint| *breakpoint != pattern);
+        self.opcode_breakpoints.len() != length
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        if !self.watchpoints.contains(&watchpoint) {
+            self.watchpoints.push(watchpoint);
+        }
+    }
+
+    #[doc = "Remove the watchpoint at the given index of watchpoints()"]
+    pub fn remove_watchpoint(&mut self, index: usize) -> Option<Watchpoint> {
+        (index < self.watchpoints.len()).then(|| self.watchpoints.remove(index))
+    }
+
+    #[doc = "Execute up to the given number of instructions, breakpoints are ignored but watchpoints still stop"]
+    pub fn step(&mut self, emulator: &mut Emulator, count: u32) -> DebugStop {
+        for _ in 0..count {
+            if let Some(stop) = self.execute(emulator) {
+                return stop;
+            }
+        }
+
+        DebugStop::Done
+    }
+
+    #[doc = "Like step, but a CALL runs until its subroutine returns"]
+    pub fn step_over(&mut self, emulator: &mut Emulator, limit: u32) -> DebugStop {
+        match emulator.current_instruction() {
+            Ok(Instruction::Call { .. }) => {
+                let return_address = emulator.pc().wrapping_add(2);
+                let sp = emulator.sp();
+
+                self.run_until(emulator, limit, |emulator| emulator.pc() == return_address && emulator.sp() == sp)
+            },
+            _ => self.step(emulator, 1),
+        }
+    }
+
+    #[doc = "Run until the current subroutine returns to its caller"]
+    pub fn run_to_return(&mut self, emulator: &mut Emulator, limit: u32) -> DebugStop {
+        if emulator.sp() == 0 {
+            return self.resume(emulator, limit);
+        }
+
+        let sp = emulator.sp() - 1;
+        self.run_until(emulator, limit, |emulator| emulator.sp() == sp)
+    }
+
+    #[doc = "Run until a breakpoint is hit, the instruction at the program counter runs even if it has a breakpoint"]
+    pub fn resume(&mut self, emulator: &mut Emulator, limit: u32) -> DebugStop {
+        self.run_until(emulator, limit, |_| false)
+    }
+
+    fn run_until(&mut self, emulator: &mut Emulator, limit: u32, done: impl Fn(&Emulator) -> bool) -> DebugStop {
+        for executed in 0..limit {
+            if executed > 0 {
+                if let Some(stop) = self.check_breakpoints(emulator) {
+                    return stop;
+                }
+            }
+
+            if let Some(stop) = self.execute(emulator) {
+                return stop;
+            }
+
+            if done(emulator) {
+                return DebugStop::Done;
+            }
+
+            if let Ok(Instruction::Jp { addr }) = emulator.current_instruction() {
+                if addr == emulator.pc() {
+                    return DebugStop::SelfLoop(addr);
+                }
+            }
+        }
+
+        DebugStop::Limit
+    }
+
+    fn check_breakpoints(&self, emulator: &Emulator) -> Option<DebugStop> {
+        let pc = emulator.pc();
+
+        if self.breakpoints.contains(&pc) {
+            return Some(DebugStop::Breakpoint(pc));
+        }
+
+        let memory = emulator.memory();
+        let opcode = ((*memory.get(pc as usize)? as u16) << 8) | (*memory.get(pc as usize + 1)? as u16);
+        if self.opcode_breakpoints.iter().any(|pattern| pattern.matches(opcode)) {
+            return Some(DebugStop::OpcodeBreakpoint { pc, opcode });
+        }
+
+        None
+    }
+
+    #[doc = "Execute one instruction, ticking the timers at the end of every frame"]
+    fn execute(&mut self, emulator: &mut Emulator) -> Option<DebugStop> {
+        let pc = emulator.pc();
+        let registers = *emulator.registers();
+        let i = emulator.i();
+
+        let outcome = emulator.next_cycle();
+
+        self.frame_cycles += 1;
+        if self.frame_cycles >= self.instructions_per_frame || outcome == Ok(StepOutcome::WaitingForVblank) {
+            emulator.tick_timers();
+            self.frame_cycles = 0;
+        }
+
+        if outcome.is_ok() {
+            let triggered = self.watchpoints.iter().find(|watchpoint| is_triggered(watchpoint, emulator, &registers, i));
+
+            if let Some(watchpoint) = triggered {
+                return Some(DebugStop::Watchpoint { watchpoint: *watchpoint, pc });
+            }
+        }
+
+        match outcome {
+            Ok(StepOutcome::Executed) | Ok(StepOutcome::WaitingForVblank) => None,
+            Ok(StepOutcome::WaitingForKey) => Some(DebugStop::WaitingForKey),
+            Ok(StepOutcome::Exit) => Some(DebugStop::Exit),
+            Err(error) => Some(DebugStop::Crashed(error)),
+        }
+    }
+}
+
+#[doc = "Check a watchpoint against the state before the last instruction"]
+fn is_triggered(watchpoint: &Watchpoint, emulator: &Emulator, registers_before: &[u8; 16], i_before: u16) -> bool {
+    match *watchpoint {
+        Watchpoint::Memory { start, end, read, write } => emulator.memory_accesses().iter().any(|access| {
+            let kind_matches = match access.kind {
+                MemoryAccessKind::Read => read,
+                MemoryAccessKind::Write => write,
+            };
+            let access_end = access.address as usize + access.length as usize;
+
+            kind_matches && access.length > 0 && (access.address as usize) <= end as usize && access_end > start as usize
+        }),
+        Watchpoint::RegisterChanged(x) => emulator.registers()[x as usize] != registers_before[x as usize],
+        Watchpoint::RegisterEquals(x, value) => emulator.registers()[x as usize] == value && registers_before[x as usize] != value,
+        Watchpoint::IInRange { start, end } => (start..=end).contains(&emulator.i()) && !(start..=end).contains(&i_before),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Quirks, Variant};
+
+    // 0x200: CALL 0x208 / LD V0, 1 / JP 0x204
+    // 0x208: LD V1, 2 / CALL 0x20E / RET
+    // 0x20E: LD V2, 3 / RET
+    const ROM: [u8; 18] = [0x22, 0x08, 0x60, 0x01, 0x12, 0x04, 0x00, 0x00, 0x61, 0x02, 0x22, 0x0E, 0x00, 0xEE, 0x62, 0x03, 0x00, 0xEE];
+
+    fn emulator() -> Emulator {
+        Emulator::new(ROM.to_vec(), Variant::Chip8, Quirks::default()).unwrap()
+    }
+
+    #[test]
+    fn breakpoints() {
+        let mut emulator = emulator();
+        let mut debugger = Debugger::new(10);
+        debugger.add_breakpoint(0x20E);
+
+        assert_eq!(debugger.resume(&mut emulator, 100), DebugStop::Breakpoint(0x20E));
+        assert_eq!(emulator.registers()[..3], [0, 2, 0]);
+        assert_eq!(emulator.sp(), 2);
+
+        // The instruction at the breakpoint runs when resuming from it
+        assert!(debugger.remove_breakpoint(0x20E));
+        assert!(!debugger.remove_breakpoint(0x20E));
+        debugger.add_opcode_breakpoint("60??".parse().unwrap());
+        assert_eq!(debugger.resume(&mut emulator, 100), DebugStop::OpcodeBreakpoint { pc: 0x202, opcode: 0x6001 });
+        assert_eq!(debugger.resume(&mut emulator, 100), DebugStop::SelfLoop(0x204));
+        assert_eq!(emulator.registers()[..3], [1, 2, 3]);
+    }
+
+    #[test]
+    fn step_over_a_call() {
+        let mut emulator = emulator();
+        let mut debugger = Debugger::new(10);
+
+        assert_eq!(debugger.step_over(&mut emulator, 100), DebugStop::Done);
+        assert_eq!(emulator.pc(), 0x202);
+        assert_eq!(emulator.sp(), 0);
+        assert_eq!(emulator.registers()[..3], [0, 2, 3]);
+
+        // Anything else is a single step
+        assert_eq!(debugger.step_over(&mut emulator, 100), DebugStop::Done);
+        assert_eq!(emulator.pc(), 0x204);
+    }
+
+    #[test]
+    fn step_over_stops_at_breakpoints_in_the_call() {
+        let mut emulator = emulator();
+        let mut debugger = Debugger::new(10);
+        debugger.add_breakpoint(0x20E);
+
+        assert_eq!(debugger.step_over(&mut emulator, 100), DebugStop::Breakpoint(0x20E));
+        assert_eq!(debugger.step_over(&mut emulator, 1), DebugStop::Done);
+        assert_eq!(emulator.pc(), 0x210);
+    }
+
+    #[test]
+    fn run_to_return() {
+        let mut emulator = emulator();
+        let mut debugger = Debugger::new(10);
+
+        // Inside the first subroutine, before it calls the second one
+        assert_eq!(debugger.step(&mut emulator, 2), DebugStop::Done);
+        assert_eq!(emulator.pc(), 0x20A);
+
+        assert_eq!(debugger.run_to_return(&mut emulator, 100), DebugStop::Done);
+        assert_eq!(emulator.pc(), 0x202);
+        assert_eq!(emulator.sp(), 0);
+        assert_eq!(emulator.registers()[..3], [0, 2, 3]);
+
+        // Outside of any subroutine it just resumes
+        assert_eq!(debugger.run_to_return(&mut emulator, 100), DebugStop::SelfLoop(0x204));
+    }
+
+    #[doc = "Stop of stepping over LD I, 0x300 followed by the instruction, with only the watchpoint set"]
+    fn watched(opcode: u16, watchpoint: Watchpoint) -> DebugStop {
+        let [high, low] = opcode.to_be_bytes();
+        let mut emulator = Emulator::new(vec![0xA3, 0x00, high, low], Variant::Chip8, Quirks::default()).unwrap();
+        let mut debugger = Debugger::new(10);
+        debugger.add_watchpoint(watchpoint);
+
+        debugger.step(&mut emulator, 2)
+    }
+
+    fn memory(start: u16, end: u16, read: bool, write: bool) -> Watchpoint {
+        Watchpoint::Memory { start, end, read, write }
+    }
+
+    #[test]
+    fn read_watchpoints() {
+        // LD V0..V1, [I] reads 0x300 and 0x301
+        let watchpoint = memory(0x301, 0x310, true, false);
+        assert_eq!(watched(0xF165, watchpoint), DebugStop::Watchpoint { watchpoint, pc: 0x202 });
+        assert_eq!(watched(0xF165, memory(0x302, 0x310, true, false)), DebugStop::Done);
+        assert_eq!(watched(0xF165, memory(0x2F0, 0x2FF, true, false)), DebugStop::Done);
+
+        // DRW V0, V0, 5 reads 0x300 to 0x304
+        let watchpoint = memory(0x304, 0x304, true, false);
+        assert_eq!(watched(0xD005, watchpoint), DebugStop::Watchpoint { watchpoint, pc: 0x202 });
+        assert_eq!(watched(0xD004, watchpoint), DebugStop::Done);
+
+        // Writes don't trigger read watchpoints
+        assert_eq!(watched(0xF155, memory(0x300, 0x300, true, false)), DebugStop::Done);
+    }
+
+    #[test]
+    fn write_watchpoints() {
+        // LD [I], V0..V1 writes 0x300 and 0x301
+        let watchpoint = memory(0x2F0, 0x300, false, true);
+        assert_eq!(watched(0xF155, watchpoint), DebugStop::Watchpoint { watchpoint, pc: 0x202 });
+        assert_eq!(watched(0xF155, memory(0x302, 0x310, false, true)), DebugStop::Done);
+
+        // LD B, V0 writes 0x300 to 0x302
+        let watchpoint = memory(0x302, 0x302, false, true);
+        assert_eq!(watched(0xF033, watchpoint), DebugStop::Watchpoint { watchpoint, pc: 0x202 });
+        assert_eq!(watched(0xF033, memory(0x303, 0x303, false, true)), DebugStop::Done);
+
+        // Reads don't trigger write watchpoints
+        assert_eq!(watched(0xF165, memory(0x300, 0x300, false, true)), DebugStop::Done);
+    }
+}
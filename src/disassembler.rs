@@ -0,0 +1,161 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+
+use crate::instruction::{decode_at, Instruction};
+
+#[doc = "A single decoded instruction, or raw data if the bytes aren't a known instruction"]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DisassembledLine {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    pub text: String,
+}
+
+#[doc = "Disassembled program, printed as one `0x200: LD V1, 0x0A` line per instruction with labels for jump and call targets"]
+#[derive(Debug, Clone, Default)]
+pub struct Disassembly {
+    pub lines: Vec<DisassembledLine>,
+    pub labels: BTreeMap<u16, String>,
+}
+
+impl fmt::Display for Disassembly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for line in self.lines.iter() {
+            if let Some(label) = self.labels.get(&line.address) {
+                writeln!(f, "{}:", label)?;
+            }
+
+            writeln!(f, "0x{:03X}: {}", line.address, line.text)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[doc = "Disassemble a program loaded at the given address with a linear sweep"]
+pub fn disassemble(program: &[u8], origin: u16) -> Disassembly {
+    let mut decoded: Vec<(u16, Vec<u8>)> = Vec::new();
+    let mut offset = 0;
+
+    while offset < program.len() {
+        let length = instruction_length(&program[offset..]);
+        decoded.push((origin.wrapping_add(offset as u16), program[offset..offset + length].to_vec()));
+        offset += length;
+    }
+
+    // Only targets landing on the start of a decoded instruction get a label
+    let addresses: HashSet<u16> = decoded.iter().map(|(address, _)| *address).collect();
+    let mut labels = BTreeMap::new();
+    for (_, bytes) in decoded.iter() {
+        if let Some((target, is_call)) = branch_target(bytes) {
+            if addresses.contains(&target) {
+                let label = if is_call { format!("sub_{:03X}", target) } else { format!("loc_{:03X}", target) };
+                let entry = labels.entry(target).or_insert(label.clone());
+                if is_call {
+                    *entry = label;
+                }
+            }
+        }
+    }
+
+    let lines = decoded.into_iter().map(|(address, bytes)| {
+        let text = format_bytes(&bytes, &|target| labels.get(&target).cloned());
+        DisassembledLine { address, bytes, text }
+    }).collect();
+
+    Disassembly { lines, labels }
+}
+
+#[doc = "Disassemble the instruction at the start of the given memory, returning its text and length in bytes"]
+pub fn disassemble_instruction(memory: &[u8]) -> (String, usize) {
+    let length = instruction_length(memory);
+
+    (format_bytes(&memory[..length], &|_| None), length)
+}
+
+fn instruction_length(memory: &[u8]) -> usize {
+    // A long instruction cut short by the end of the program doesn't decode and is left as data
+    match decode_at(memory) {
+        Ok(instruction) => instruction.length() as usize,
+        Err(_) => memory.len().min(2),
+    }
+}
+
+#[doc = "Address a JP or CALL instruction branches to, and whether it is a CALL"]
+fn branch_target(bytes: &[u8]) -> Option<(u16, bool)> {
+    if bytes.len() != 2 {
+        return None;
+    }
+
+    match decode_at(bytes) {
+        Ok(Instruction::Jp { addr }) => Some((addr, false)),
+        Ok(Instruction::Call { addr }) => Some((addr, true)),
+        _ => None,
+    }
+}
+
+fn format_bytes(bytes: &[u8], label_for: &dyn Fn(u16) -> Option<String>) -> String {
+    match (decode_at(bytes), bytes) {
+        (Ok(instruction), _) => format_instruction(&instruction, label_for),
+        (Err(_), [first, second]) => format!("DW 0x{:02X}{:02X}", first, second),
+        _ => bytes.iter().map(|byte| format!("DB 0x{:02X}", byte)).collect::<Vec<String>>().join("; "),
+    }
+}
+
+#[doc = "Text of an instruction, with the label of a JP or CALL target if it has one"]
+pub fn format_instruction(instruction: &Instruction, label_for: &dyn Fn(u16) -> Option<String>) -> String {
+    let target = |address: u16| label_for(address).unwrap_or_else(|| format!("0x{:03X}", address));
+
+    match *instruction {
+        Instruction::Cls => "CLS".to_string(),
+        Instruction::Ret => "RET".to_string(),
+        Instruction::ScdN { n } => format!("SCD 0x{:X}", n),
+        Instruction::ScuN { n } => format!("SCU 0x{:X}", n),
+        Instruction::Scr => "SCR".to_string(),
+        Instruction::Scl => "SCL".to_string(),
+        Instruction::Exit => "EXIT".to_string(),
+        Instruction::Low => "LOW".to_string(),
+        Instruction::High => "HIGH".to_string(),
+        Instruction::Jp { addr } => format!("JP {}", target(addr)),
+        Instruction::Call { addr } => format!("CALL {}", target(addr)),
+        Instruction::SeVxByte { x, byte } => format!("SE V{:X}, 0x{:02X}", x, byte),
+        Instruction::SneVxByte { x, byte } => format!("SNE V{:X}, 0x{:02X}", x, byte),
+        Instruction::SeVxVy { x, y } => format!("SE V{:X}, V{:X}", x, y),
+        Instruction::SaveVxVy { x, y } => format!("SAVE V{:X}, V{:X}", x, y),
+        Instruction::LoadVxVy { x, y } => format!("LOAD V{:X}, V{:X}", x, y),
+        Instruction::LdVxByte { x, byte } => format!("LD V{:X}, 0x{:02X}", x, byte),
+        Instruction::AddVxByte { x, byte } => format!("ADD V{:X}, 0x{:02X}", x, byte),
+        Instruction::LdVxVy { x, y } => format!("LD V{:X}, V{:X}", x, y),
+        Instruction::OrVxVy { x, y } => format!("OR V{:X}, V{:X}", x, y),
+        Instruction::AndVxVy { x, y } => format!("AND V{:X}, V{:X}", x, y),
+        Instruction::XorVxVy { x, y } => format!("XOR V{:X}, V{:X}", x, y),
+        Instruction::AddVxVy { x, y } => format!("ADD V{:X}, V{:X}", x, y),
+        Instruction::SubVxVy { x, y } => format!("SUB V{:X}, V{:X}", x, y),
+        Instruction::ShrVxVy { x, y } => format!("SHR V{:X}, V{:X}", x, y),
+        Instruction::SubnVxVy { x, y } => format!("SUBN V{:X}, V{:X}", x, y),
+        Instruction::ShlVxVy { x, y } => format!("SHL V{:X}, V{:X}", x, y),
+        Instruction::SneVxVy { x, y } => format!("SNE V{:X}, V{:X}", x, y),
+        Instruction::LdIAddr { addr } => format!("LD I, 0x{:03X}", addr),
+        Instruction::JpV0Addr { addr, .. } => format!("JP V0, 0x{:03X}", addr),
+        Instruction::RndVxByte { x, byte } => format!("RND V{:X}, 0x{:02X}", x, byte),
+        Instruction::DrwVxVyN { x, y, n } => format!("DRW V{:X}, V{:X}, 0x{:X}", x, y, n),
+        Instruction::SkpVx { x } => format!("SKP V{:X}", x),
+        Instruction::SknpVx { x } => format!("SKNP V{:X}", x),
+        Instruction::LdILong { addr } => format!("LD I, LONG 0x{:04X}", addr),
+        Instruction::Plane { planes } => format!("PLANE 0x{:X}", planes),
+        Instruction::Audio => "AUDIO".to_string(),
+        Instruction::LdVxDt { x } => format!("LD V{:X}, DT", x),
+        Instruction::LdVxK { x } => format!("LD V{:X}, K", x),
+        Instruction::LdDtVx { x } => format!("LD DT, V{:X}", x),
+        Instruction::LdStVx { x } => format!("LD ST, V{:X}", x),
+        Instruction::AddIVx { x } => format!("ADD I, V{:X}", x),
+        Instruction::LdFVx { x } => format!("LD F, V{:X}", x),
+        Instruction::LdHfVx { x } => format!("LD HF, V{:X}", x),
+        Instruction::LdBVx { x } => format!("LD B, V{:X}", x),
+        Instruction::PitchVx { x } => format!("PITCH V{:X}", x),
+        Instruction::LdIVx { x } => format!("LD [I], V{:X}", x),
+        Instruction::LdVxI { x } => format!("LD V{:X}, [I]", x),
+        Instruction::LdRVx { x } => format!("LD R, V{:X}", x),
+        Instruction::LdVxR { x } => format!("LD V{:X}, R", x),
+    }
+}
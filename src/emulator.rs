@@ -1,6 +1,14 @@
-use sdl2::keyboard::Scancode;
-
-use crate::opcode::{Opcode, ZeroOpcode, EightOpcode, FifteenOpcode, FourteenOpcode};
+use std::collections::VecDeque;
+
+use crate::checksum::{crc32, fnv1a_64};
+use crate::error::{EmulatorError, StepOutcome};
+use crate::keypad::{KeyEvent, Keypad};
+use crate::quirks::{MemoryIncrement, Quirks};
+use crate::rng::{RandomSource, XorShift};
+use crate::save_state::{SaveStateError, SaveStateHeader, StateReader, HEADER_SIZE, VERSION};
+use crate::trace::Tracer;
+use crate::variant::Variant;
+use crate::instruction::{decode_at, Instruction};
 
 const FONT: [u8; 80] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
@@ -21,11 +29,64 @@ const FONT: [u8; 80] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80  // F
 ];
 
+const BIG_FONT: [u8; 160] = [
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0  // F
+];
+
+const FONT_ADDRESS: u16 = 0x50;
+const BIG_FONT_ADDRESS: u16 = 0xA0;
+
+#[doc = "Address the rom is loaded at and execution starts from"]
+pub const PROGRAM_ADDRESS: u16 = 0x200;
+
+#[doc = "Size of the video memory, the low resolution mode uses its top-left 64x32 corner"]
+pub const SCREEN_WIDTH: usize = 128;
+pub const SCREEN_HEIGHT: usize = 64;
+
+#[doc = "Number of XO-CHIP bitplanes, each pixel of the video memory holds one bit per plane"]
+pub const PLANE_COUNT: usize = 2;
+
+#[doc = "XO-CHIP pitch register value that plays the audio pattern at 4000 bits per second"]
+const DEFAULT_PITCH: u8 = 64;
+
+#[doc = "Whether an instruction read or wrote memory"]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryAccessKind {
+    Read,
+    Write,
+}
+
+#[doc = "Memory range accessed by an instruction, instruction fetches excluded"]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryAccess {
+    pub kind: MemoryAccessKind,
+    pub address: u16,
+    pub length: u16,
+}
+
 pub struct Emulator {
     // Stack, ram, etc...
     stack: [u16; 16],
-    memory: [u8; 4096],
-    video_memory: [[bool; 32]; 64],
+    memory: Vec<u8>,
+    video_memory: [[u8; SCREEN_HEIGHT]; SCREEN_WIDTH],
+    hires: bool,
+    #[doc = "Bitplanes affected by drawing, clearing and scrolling"]
+    selected_planes: u8,
     // Pseudo-Registers
     sp: u8,
     pc: u16,
@@ -34,473 +95,1424 @@ pub struct Emulator {
     i: u16,
     timers: [u8; 2],
     // Others
-    scancodes: Vec<Scancode>
+    keypad: Keypad,
+    #[doc = "Presses and releases since the last frame, so LD Vx, K sees keys tapped between two frames"]
+    key_events: VecDeque<KeyEvent>,
+    #[doc = "Key pressed while executing LD Vx, K, stored until it is released"]
+    waiting_key: Option<u8>,
+    rng: Box<dyn RandomSource>,
+    #[doc = "State the RNG started from, recorded in movies"]
+    rng_seed: u32,
+    #[doc = "SUPER-CHIP RPL user flags, persisted by the frontend"]
+    rpl_flags: [u8; 16],
+    #[doc = "XO-CHIP 1-bit audio pattern, None until the program loads one"]
+    audio_pattern: Option<[u8; 16]>,
+    pitch: u8,
+    variant: Variant,
+    quirks: Quirks,
+    #[doc = "Hash of the loaded rom, save states of other roms are rejected"]
+    rom_hash: u64,
+    #[doc = "Memory accessed by the last instruction, for watchpoints"]
+    memory_accesses: Vec<MemoryAccess>,
+    #[doc = "Instructions executed since the start, not part of save states"]
+    cycles: u64,
+    tracer: Option<Tracer>
 }
 
 impl Emulator {
-    pub fn new(rom: Vec<u8>) -> Self {
+    #[doc = "Fails if the rom doesn't fit in the memory of the variant"]
+    pub fn new(rom: Vec<u8>, variant: Variant, quirks: Quirks) -> Result<Self, EmulatorError> {
+        let max = variant.memory_size() - PROGRAM_ADDRESS as usize;
+        if rom.len() > max {
+            return Err(EmulatorError::RomTooLarge { size: rom.len(), max });
+        }
+
+        let rng = XorShift::from_time();
+
         let mut emulator = Self {
             stack: [0; 16],
-            memory: [0; 4096],
-            video_memory: [[false; 32]; 64],
+            memory: vec![0; variant.memory_size()],
+            video_memory: [[0; SCREEN_HEIGHT]; SCREEN_WIDTH],
+            hires: false,
+            selected_planes: 0b01,
 
             sp: 0,
-            pc: 0x200, // 512 in decimal
+            pc: PROGRAM_ADDRESS,
 
             vx: [0; 16],
             i: 0,
             timers: [0; 2],
 
-            scancodes: vec![]
+            keypad: Keypad::new(),
+            key_events: VecDeque::new(),
+            waiting_key: None,
+            rng_seed: rng.state(),
+            rng: Box::new(rng),
+            rpl_flags: [0; 16],
+            audio_pattern: None,
+            pitch: DEFAULT_PITCH,
+            variant,
+            quirks,
+            rom_hash: fnv1a_64(&rom),
+            memory_accesses: Vec::new(),
+            cycles: 0,
+            tracer: None
         };
 
         for (i, byte) in FONT.iter().enumerate() {
-            emulator.memory[0x50 + i] = *byte;
+            emulator.memory[FONT_ADDRESS as usize + i] = *byte;
+        }
+
+        for (i, byte) in BIG_FONT.iter().enumerate() {
+            emulator.memory[BIG_FONT_ADDRESS as usize + i] = *byte;
         }
 
         for (i, byte) in rom.iter().enumerate() {
-            emulator.memory[0x200 + i] = *byte;
+            emulator.memory[PROGRAM_ADDRESS as usize + i] = *byte;
         }
 
-        emulator
+        Ok(emulator)
+    }
+
+    #[doc = "Video memory indexed by [x][y], only the area given by resolution() is in use"]
+    #[doc = ""]
+    #[doc = "Every pixel holds one bit per bitplane, so plain CHIP-8 pixels are either 0 or 1"]
+    pub fn video_memory(&self) -> &[[u8; SCREEN_HEIGHT]; SCREEN_WIDTH] {
+        &self.video_memory
+    }
+
+    #[doc = "Active resolution as (width, height), 128x64 in SUPER-CHIP hi-res mode and 64x32 otherwise"]
+    pub fn resolution(&self) -> (usize, usize) {
+        if self.hires {
+            (SCREEN_WIDTH, SCREEN_HEIGHT)
+        } else {
+            (SCREEN_WIDTH / 2, SCREEN_HEIGHT / 2)
+        }
+    }
+
+    pub fn rpl_flags(&self) -> [u8; 16] {
+        self.rpl_flags
     }
 
-    pub fn video_memory(&self) -> [[bool; 32]; 64] {
-        self.video_memory
+    pub fn set_rpl_flags(&mut self, rpl_flags: [u8; 16]) {
+        self.rpl_flags = rpl_flags;
     }
 
-    pub fn next_cycle(&mut self) {
-        // Decrement the timers
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    #[doc = "Number of return addresses on the stack"]
+    pub fn sp(&self) -> u8 {
+        self.sp
+    }
+
+    #[doc = "Return addresses on the stack, the innermost call last"]
+    pub fn stack(&self) -> &[u16] {
+        &self.stack[..self.sp as usize]
+    }
+
+    pub fn i(&self) -> u16 {
+        self.i
+    }
+
+    pub fn set_i(&mut self, i: u16) {
+        self.i = i;
+    }
+
+    #[doc = "Registers V0 to VF"]
+    pub fn registers(&self) -> &[u8; 16] {
+        &self.vx
+    }
+
+    pub fn set_register(&mut self, x: u8, value: u8) {
+        self.vx[x as usize & 0x0F] = value;
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.timers[0]
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.timers[1]
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    pub fn variant(&self) -> Variant {
+        self.variant
+    }
+
+    #[doc = "Write to memory on behalf of a debugger, the write isn't part of memory_accesses()"]
+    pub fn write_memory(&mut self, address: u16, bytes: &[u8]) -> Result<(), EmulatorError> {
+        let start = address as usize;
+        let end = start + bytes.len();
+
+        if end > self.memory.len() {
+            return Err(EmulatorError::MemoryOutOfBounds { pc: self.pc, address: end - 1 });
+        }
+
+        self.memory[start..end].copy_from_slice(bytes);
+        Ok(())
+    }
+
+    #[doc = "Number of instructions executed by next_cycle()"]
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    #[doc = "Trace the instructions executed by next_cycle(), None stops tracing and drops the tracer"]
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.tracer = tracer;
+    }
+
+    #[doc = "Memory read and written by the last instruction executed"]
+    pub fn memory_accesses(&self) -> &[MemoryAccess] {
+        &self.memory_accesses
+    }
+
+    #[doc = "Decode the instruction at the program counter without executing it"]
+    pub fn current_instruction(&self) -> Result<Instruction, EmulatorError> {
+        self.fetch_instruction()
+    }
+
+    pub fn rom_hash(&self) -> u64 {
+        self.rom_hash
+    }
+
+    pub fn rng_seed(&self) -> u32 {
+        self.rng_seed
+    }
+
+    #[doc = "Restart the default xorshift RNG from a seed, used to make runs reproducible"]
+    pub fn set_rng_seed(&mut self, seed: u32) {
+        self.set_rng(Box::new(XorShift::new(seed)));
+    }
+
+    #[doc = "Replace the RNG, e.g. with a scripted one in tests, its current state becomes the seed"]
+    pub fn set_rng(&mut self, rng: Box<dyn RandomSource>) {
+        self.rng_seed = rng.state();
+        self.rng = rng;
+    }
+
+    #[doc = "FNV-1a hash of the resolution and the whole video memory, to compare screens cheaply"]
+    pub fn screen_hash(&self) -> u64 {
+        let mut screen = Vec::with_capacity(1 + SCREEN_WIDTH * SCREEN_HEIGHT);
+
+        screen.push(self.hires as u8);
+        self.video_memory.iter().for_each(|column| screen.extend_from_slice(column));
+        fnv1a_64(&screen)
+    }
+
+    #[doc = "Serialize the machine state, see SaveStateHeader for the layout"]
+    #[doc = ""]
+    #[doc = "The quirks and the keypad aren't part of the state, they belong to the frontend"]
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut payload = Vec::with_capacity(self.memory.len() + SCREEN_WIDTH * SCREEN_HEIGHT + 128);
+
+        payload.extend_from_slice(&self.pc.to_le_bytes());
+        payload.extend_from_slice(&self.i.to_le_bytes());
+        payload.push(self.sp);
+        payload.extend_from_slice(&self.vx);
+        self.stack.iter().for_each(|value| payload.extend_from_slice(&value.to_le_bytes()));
+        payload.extend_from_slice(&self.timers);
+        payload.push(self.hires as u8);
+        payload.push(self.selected_planes);
+        payload.push(self.pitch);
+        payload.push(self.waiting_key.unwrap_or(0xFF));
+        payload.push(self.audio_pattern.is_some() as u8);
+        payload.extend_from_slice(&self.audio_pattern.unwrap_or_default());
+        payload.extend_from_slice(&self.rpl_flags);
+        payload.extend_from_slice(&self.rng.state().to_le_bytes());
+        payload.extend_from_slice(&(self.memory.len() as u32).to_le_bytes());
+        payload.extend_from_slice(&self.memory);
+        self.video_memory.iter().for_each(|column| payload.extend_from_slice(column));
+
+        let header = SaveStateHeader {
+            version: VERSION,
+            variant: self.variant,
+            rom_hash: self.rom_hash,
+            payload_length: payload.len() as u32,
+            checksum: crc32(&payload),
+        };
+
+        let mut state = Vec::with_capacity(HEADER_SIZE + payload.len());
+        header.write(&mut state);
+        state.extend_from_slice(&payload);
+        state
+    }
+
+    #[doc = "Restore a state made by save_state(), the emulator is left untouched if it fails"]
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), SaveStateError> {
+        let mut reader = StateReader::new(state);
+        let header = SaveStateHeader::read(&mut reader)?;
+
+        if header.variant != self.variant {
+            return Err(SaveStateError::VariantMismatch { expected: self.variant, found: header.variant });
+        }
+
+        if header.rom_hash != self.rom_hash {
+            return Err(SaveStateError::RomMismatch { expected: self.rom_hash, found: header.rom_hash });
+        }
+
+        let payload = reader.bytes(header.payload_length as usize)?;
+        if crc32(payload) != header.checksum {
+            return Err(SaveStateError::ChecksumMismatch);
+        }
+
+        let mut reader = StateReader::new(payload);
+
+        let pc = reader.u16()?;
+        let i = reader.u16()?;
+        let sp = reader.u8()?;
+        let vx = reader.array()?;
+        let mut stack = [0; 16];
+        for value in stack.iter_mut() {
+            *value = reader.u16()?;
+        }
+        let timers = reader.array()?;
+        let hires = reader.u8()? != 0;
+        let selected_planes = reader.u8()?;
+        let pitch = reader.u8()?;
+        let waiting_key = match reader.u8()? {
+            0xFF => None,
+            key => Some(key),
+        };
+        let has_audio_pattern = reader.u8()? != 0;
+        let audio_pattern = reader.array()?;
+        let rpl_flags = reader.array()?;
+        let rng_state = reader.u32()?;
+        let memory_length = reader.u32()? as usize;
+        let memory = reader.bytes(memory_length)?;
+        let mut video_memory = [[0; SCREEN_HEIGHT]; SCREEN_WIDTH];
+        for column in video_memory.iter_mut() {
+            *column = reader.array()?;
+        }
+
+        if sp as usize > stack.len() {
+            return Err(SaveStateError::InvalidPayload("stack pointer"));
+        }
+
+        if waiting_key.is_some_and(|key| key > 0xF) {
+            return Err(SaveStateError::InvalidPayload("waiting key"));
+        }
+
+        if memory_length != self.memory.len() {
+            return Err(SaveStateError::InvalidPayload("memory size"));
+        }
+
+        self.pc = pc;
+        self.i = i;
+        self.sp = sp;
+        self.vx = vx;
+        self.stack = stack;
+        self.timers = timers;
+        self.hires = hires;
+        self.selected_planes = selected_planes & 0b11;
+        self.pitch = pitch;
+        self.waiting_key = waiting_key;
+        self.audio_pattern = if has_audio_pattern { Some(audio_pattern) } else { None };
+        self.rpl_flags = rpl_flags;
+        self.rng.set_state(rng_state);
+        self.memory.copy_from_slice(memory);
+        self.video_memory = video_memory;
+
+        Ok(())
+    }
+
+    #[doc = "Run one 60 Hz frame: the given number of instructions followed by a timer tick"]
+    #[doc = ""]
+    #[doc = "Returns StepOutcome::Exit if the program exited during the frame"]
+    pub fn run_frame(&mut self, instructions_per_frame: u32) -> Result<StepOutcome, EmulatorError> {
+        for _ in 0..instructions_per_frame {
+            match self.next_cycle()? {
+                StepOutcome::Exit => return Ok(StepOutcome::Exit),
+                StepOutcome::WaitingForVblank => break,
+                _ => {}
+            }
+        }
+
+        self.tick_timers();
+
+        Ok(StepOutcome::Executed)
+    }
+
+    #[doc = "XO-CHIP audio pattern played instead of the plain beep, if the program loaded one"]
+    pub fn audio_pattern(&self) -> Option<&[u8; 16]> {
+        self.audio_pattern.as_ref()
+    }
+
+    #[doc = "Rate in bits per second at which the XO-CHIP audio pattern is played"]
+    pub fn audio_playback_rate(&self) -> f32 {
+        4000.0 * 2f32.powf((self.pitch as f32 - 64.0) / 48.0)
+    }
+
+    #[doc = "Whether the beeper should sound, which is the case while the sound timer is non-zero"]
+    pub fn sound_active(&self) -> bool {
+        self.timers[1] > 0
+    }
+
+    #[doc = "Decrement the delay and sound timers, meant to be called at 60 Hz"]
+    pub fn tick_timers(&mut self) {
         self.timers.iter_mut().for_each(|timer| {
-            if *timer > 1 {
+            if *timer > 0 {
                 *timer -= 1;
             }
         });
 
-        let opcode = self.fetch_opcode();
-        match num::FromPrimitive::from_u16(opcode & 0xF000) {
-            Some(Opcode::ZeroOpcode) => {
-                match num::FromPrimitive::from_u16(opcode & 0x00FF) {
-                    Some(ZeroOpcode::CLS) => {
-                        self.clear_screen();
-                        self.pc += 2;
-                    },
-                    Some(ZeroOpcode::RET) => {
-                        self.pc = self.pop();
-                        self.pc += 2;
-                    },
-                    _ => {
-                        unreachable!();
-                    }
+        // Key events older than a frame would let LD Vx, K accept a press made before it ran
+        self.key_events.clear();
+    }
+
+    #[doc = "Fetch, decode and execute the instruction at the program counter"]
+    pub fn next_cycle(&mut self) -> Result<StepOutcome, EmulatorError> {
+        let Some(mut tracer) = self.tracer.take() else {
+            return self.run_cycle();
+        };
+
+        let before = tracer.before(self);
+        let result = self.run_cycle();
+        tracer.after(&before, self, &result);
+
+        self.tracer = Some(tracer);
+        result
+    }
+
+    fn run_cycle(&mut self) -> Result<StepOutcome, EmulatorError> {
+        self.memory_accesses.clear();
+
+        let instruction = self.fetch_instruction()?;
+
+        if !self.variant.supports(&instruction) {
+            return Err(EmulatorError::UnknownOpcode { pc: self.pc, opcode: self.fetch_opcode()? });
+        }
+
+        let outcome = self.execute(&instruction)?;
+
+        self.cycles += 1;
+
+        Ok(outcome)
+    }
+
+    #[doc = "Execute an instruction as if it was located at the program counter"]
+    pub fn execute(&mut self, instruction: &Instruction) -> Result<StepOutcome, EmulatorError> {
+        let mut outcome = StepOutcome::Executed;
+
+        match *instruction {
+            Instruction::Cls => {
+                self.clear_screen();
+                self.pc = self.pc.wrapping_add(2);
+            },
+            Instruction::Ret => {
+                self.pc = self.pop()?;
+                self.pc = self.pc.wrapping_add(2);
+            },
+            Instruction::ScdN { n } => {
+                self.scroll_vertically(n as isize);
+                self.pc = self.pc.wrapping_add(2);
+            },
+            Instruction::ScuN { n } => {
+                self.scroll_vertically(-(n as isize));
+                self.pc = self.pc.wrapping_add(2);
+            },
+            Instruction::Scr => {
+                self.scroll_horizontally(4);
+                self.pc = self.pc.wrapping_add(2);
+            },
+            Instruction::Scl => {
+                self.scroll_horizontally(-4);
+                self.pc = self.pc.wrapping_add(2);
+            },
+            Instruction::Exit => {
+                // The program counter stays on 00FD, the program keeps exiting if resumed
+                outcome = StepOutcome::Exit;
+            },
+            Instruction::Low => {
+                self.hires = false;
+                self.video_memory = [[0; SCREEN_HEIGHT]; SCREEN_WIDTH];
+                self.pc = self.pc.wrapping_add(2);
+            },
+            Instruction::High => {
+                self.hires = true;
+                self.video_memory = [[0; SCREEN_HEIGHT]; SCREEN_WIDTH];
+                self.pc = self.pc.wrapping_add(2);
+            },
+            Instruction::Jp { addr } => {
+                self.jump(addr);
+            },
+            Instruction::Call { addr } => {
+                self.push(self.pc)?;
+                self.jump(addr);
+            },
+            Instruction::SeVxByte { x, byte } => {
+                if self.vx[x as usize] == byte {
+                    self.skip_next_instruction()?;
+                } else {
+                    self.pc = self.pc.wrapping_add(2);
                 }
             },
-            Some(Opcode::JpAddr) => {
-                let value = opcode & 0x0FFF;
-                self.jump(value);
+            Instruction::SneVxByte { x, byte } => {
+                if self.vx[x as usize] != byte {
+                    self.skip_next_instruction()?;
+                } else {
+                    self.pc = self.pc.wrapping_add(2);
+                }
             },
-            Some(Opcode::CallAddr) => {
-                let value = opcode & 0x0FFF;
-
-                self.push(self.pc);
-                self.jump(value);
-            }, 
-            Some(Opcode::SeVxByte) => {
-                let register_index = ((opcode & 0x0F00) >> 8) as u8;
-                let value = (opcode & 0x00FF) as u8;
-
-                if self.vx[register_index as usize] == value {
-                    self.pc += 4;
+            Instruction::SeVxVy { x, y } => {
+                if self.vx[x as usize] == self.vx[y as usize] {
+                    self.skip_next_instruction()?;
                 } else {
-                    self.pc += 2;
+                    self.pc = self.pc.wrapping_add(2);
+                }
+            },
+            Instruction::SaveVxVy { x, y } => {
+                let registers = self.register_range(x, y);
+                self.write_ram(self.i, registers)?;
+                self.pc = self.pc.wrapping_add(2);
+            },
+            Instruction::LoadVxVy { x, y } => {
+                let length = x.abs_diff(y) as u16 + 1;
+                let read_memory = self.read_ram(self.i, length)?;
+
+                // The range is walked from X to Y, even when X is the larger index
+                for (offset, value) in read_memory.iter().enumerate() {
+                    let register_index = if x <= y {
+                        x as usize + offset
+                    } else {
+                        x as usize - offset
+                    };
+
+                    self.vx[register_index] = *value;
+                }
+                self.pc = self.pc.wrapping_add(2);
+            },
+            Instruction::LdVxByte { x, byte } => {
+                self.vx[x as usize] = byte;
+                self.pc = self.pc.wrapping_add(2);
+            },
+            Instruction::AddVxByte { x, byte } => {
+                self.vx[x as usize] = self.vx[x as usize].wrapping_add(byte);
+                self.pc = self.pc.wrapping_add(2);
+            },
+            Instruction::LdVxVy { x, y } => {
+                self.vx[x as usize] = self.vx[y as usize];
+                self.pc = self.pc.wrapping_add(2);
+            },
+            Instruction::OrVxVy { x, y } => {
+                self.vx[x as usize] |= self.vx[y as usize];
+                if self.quirks.logic_resets_vf {
+                    self.vx[15] = 0;
+                }
+                self.pc = self.pc.wrapping_add(2);
+            },
+            Instruction::AndVxVy { x, y } => {
+                self.vx[x as usize] &= self.vx[y as usize];
+                if self.quirks.logic_resets_vf {
+                    self.vx[15] = 0;
+                }
+                self.pc = self.pc.wrapping_add(2);
+            },
+            Instruction::XorVxVy { x, y } => {
+                self.vx[x as usize] ^= self.vx[y as usize];
+                if self.quirks.logic_resets_vf {
+                    self.vx[15] = 0;
                 }
+                self.pc = self.pc.wrapping_add(2);
             },
-            Some(Opcode::SneVxByte) => {
-                let register_index = ((opcode & 0x0F00) >> 8) as u8;
-                let value = (opcode & 0x00FF) as u8;
+            Instruction::AddVxVy { x, y } => {
+                let vx_value_before = self.vx[x as usize];
 
-                if self.vx[register_index as usize] != value {
-                    self.pc += 4;
+                self.vx[x as usize] = self.vx[x as usize].wrapping_add(self.vx[y as usize]);
+                // Check for VX overflow
+                if self.vx[x as usize] < vx_value_before {
+                    self.vx[15] = 1;
                 } else {
-                    self.pc += 2;
+                    self.vx[15] = 0;
                 }
+                self.pc = self.pc.wrapping_add(2);
             },
-            Some(Opcode::SeVxVy) => {
-                let first_register_index = ((opcode & 0x00F0) >> 4) as u8;
-                let second_register_index = ((opcode & 0x0F00) >> 8) as u8;
+            Instruction::SubVxVy { x, y } => {
+                // VF is 1 when there is no borrow, it is written last in case X is F
+                let no_borrow = self.vx[x as usize] >= self.vx[y as usize];
 
-                if self.vx[first_register_index as usize] == self.vx[second_register_index as usize] {
-                    self.pc += 4;
+                self.vx[x as usize] = self.vx[x as usize].wrapping_sub(self.vx[y as usize]);
+                self.vx[15] = no_borrow as u8;
+                self.pc = self.pc.wrapping_add(2);
+            },
+            Instruction::ShrVxVy { x, y } => {
+                let vx_value_before = if self.quirks.shift_uses_vy {
+                    self.vx[y as usize]
+                } else {
+                    self.vx[x as usize]
+                };
+
+                self.vx[x as usize] = vx_value_before.wrapping_shr(1);
+
+                if ((vx_value_before << 7) >> 7) == 1 {
+                    self.vx[15] = 1;
                 } else {
-                    self.pc += 2;
+                    self.vx[15] = 0;
                 }
+                self.pc = self.pc.wrapping_add(2);
             },
-            Some(Opcode::LdVxByte) => {
-                let register_index = ((opcode & 0x0F00) >> 8) as u8;
-                let value = (opcode & 0x00FF) as u8;
-
-                self.vx[register_index as usize] = value;
-                self.pc += 2;
-            },
-            Some(Opcode::AddVxByte) => {
-                let register_index = ((opcode & 0x0F00) >> 8) as u8;
-                let value = (opcode & 0x00FF) as u8;
-
-                self.vx[register_index as usize] = self.vx[register_index as usize].wrapping_add(value);
-                self.pc += 2;
-            },
-            Some(Opcode::EightOpcode) => {
-                match num::FromPrimitive::from_u16(opcode & 0x000F) {
-                    Some(EightOpcode::LdVxVy) => {
-                        let first_register_index = ((opcode & 0x0F00) >> 8) as u8;
-                        let second_register_index = ((opcode & 0x00F0) >> 4) as u8;
-
-                        self.vx[first_register_index as usize] = self.vx[second_register_index as usize];
-                        self.pc += 2;
-                    },
-                    Some(EightOpcode::OrVxVy) => {
-                        let first_register_index = ((opcode & 0x0F00) >> 8) as u8;
-                        let second_register_index = ((opcode & 0x00F0) >> 4) as u8;
-
-                        self.vx[first_register_index as usize] |= self.vx[second_register_index as usize];
-                        self.pc += 2;
-                    },
-                    Some(EightOpcode::AndVxVy) => {
-                        let first_register_index = ((opcode & 0x0F00) >> 8) as u8;
-                        let second_register_index = ((opcode & 0x00F0) >> 4) as u8;
-
-                        self.vx[first_register_index as usize] &= self.vx[second_register_index as usize];
-                        self.pc += 2;
-                    },
-                    Some(EightOpcode::XorVxVy) => {
-                        let first_register_index = ((opcode & 0x0F00) >> 8) as u8;
-                        let second_register_index = ((opcode & 0x00F0) >> 4) as u8;
-
-                        self.vx[first_register_index as usize] ^= self.vx[second_register_index as usize];
-                        self.pc += 2;
-                    },
-                    Some(EightOpcode::AddVxVy) => {
-                        let first_register_index = ((opcode & 0x0F00) >> 8) as u8;
-                        let second_register_index = ((opcode & 0x00F0) >> 4) as u8;
-
-                        let vx_value_before = self.vx[first_register_index as usize];
-
-                        self.vx[first_register_index as usize] = self.vx[first_register_index as usize].wrapping_add(self.vx[second_register_index as usize]);
-                        // Check for VX overflow
-                        if self.vx[first_register_index as usize] < vx_value_before {
-                            self.vx[15] = 1;
-                        } else {
-                            self.vx[15] = 0;
-                        }
-                        self.pc += 2;
-                    },
-                    Some(EightOpcode::SubVxVy) => {
-                        let first_register_index = ((opcode & 0x0F00) >> 8) as u8;
-                        let second_register_index = ((opcode & 0x00F0) >> 4) as u8;
-
-                        let vx_value_before = self.vx[first_register_index as usize];
-
-                        self.vx[first_register_index as usize] = self.vx[first_register_index as usize].wrapping_sub(self.vx[second_register_index as usize]);
-                        // Check for VX underflow
-                        if self.vx[first_register_index as usize] < vx_value_before {
-                            self.vx[15] = 1;
-                        } else {
-                            self.vx[15] = 0;
-                        }
-                        self.pc += 2;
-                    },
-                    Some(EightOpcode::ShrVx) => {
-                        let first_register_index = ((opcode & 0x0F00) >> 8) as u8;
-                        //let second_register_index = ((opcode & 0x00F0) >> 4) as u8;
+            Instruction::SubnVxVy { x, y } => {
+                let no_borrow = self.vx[y as usize] >= self.vx[x as usize];
 
-                        let vx_value_before = self.vx[first_register_index as usize];
-
-                        self.vx[first_register_index as usize] = self.vx[first_register_index as usize].wrapping_shr(1);
+                self.vx[x as usize] = self.vx[y as usize].wrapping_sub(self.vx[x as usize]);
+                self.vx[15] = no_borrow as u8;
+                self.pc = self.pc.wrapping_add(2);
+            },
+            Instruction::ShlVxVy { x, y } => {
+                let vx_value_before = if self.quirks.shift_uses_vy {
+                    self.vx[y as usize]
+                } else {
+                    self.vx[x as usize]
+                };
 
-                        if ((vx_value_before << 7) >> 7) == 1 {
-                            self.vx[15] = 1;
-                        } else {
-                            self.vx[15] = 0;
-                        }
-                        self.pc += 2;
-                    },
-                    Some(EightOpcode::SubnVxVy) => {
-                        let first_register_index = ((opcode & 0x0F00) >> 8) as u8;
-                        let second_register_index = ((opcode & 0x00F0) >> 4) as u8;
-
-                        self.vx[first_register_index as usize] = self.vx[second_register_index as usize].wrapping_sub(self.vx[first_register_index as usize]);
-
-                        if self.vx[second_register_index as usize] > self.vx[first_register_index as usize] {
-                            self.vx[15] = 1;
-                        } else {
-                            self.vx[15] = 0;
-                        }
-                        self.pc += 2;
-                    },
-                    Some(EightOpcode::ShlVx) => {
-                        let first_register_index = ((opcode & 0x0F00) >> 8) as u8;
-                        //let second_register_index = ((opcode & 0x00F0) >> 4) as u8;
+                self.vx[x as usize] = vx_value_before.wrapping_shl(1);
 
-                        let vx_value_before = self.vx[first_register_index as usize];
+                if (vx_value_before >> 7) == 1 {
+                    self.vx[15] = 1;
+                } else {
+                    self.vx[15] = 0;
+                }
+                self.pc = self.pc.wrapping_add(2);
+            },
+            Instruction::SneVxVy { x, y } => {
+                if self.vx[x as usize] != self.vx[y as usize] {
+                    self.skip_next_instruction()?;
+                } else {
+                    self.pc = self.pc.wrapping_add(2);
+                }
+            },
+            Instruction::LdIAddr { addr } => {
+                self.i = addr;
+                self.pc = self.pc.wrapping_add(2);
+            },
+            Instruction::JpV0Addr { x, addr } => {
+                // With the quirk, BXNN jumps to XNN + VX
+                let register_index = if self.quirks.jump_uses_vx { x as usize } else { 0 };
 
-                        self.vx[first_register_index as usize] = self.vx[first_register_index as usize].wrapping_shl(1);
+                self.jump(addr + self.vx[register_index] as u16);
+            },
+            Instruction::RndVxByte { x, byte } => {
+                self.vx[x as usize] = self.rng.next_u8() & byte;
+                self.pc = self.pc.wrapping_add(2);
+            },
+            Instruction::DrwVxVyN { x, y, n } => {
+                let (width, height) = self.resolution();
+                let sprite_x = self.vx[x as usize] as usize % width;
+                let sprite_y = self.vx[y as usize] as usize % height;
+
+                // SUPER-CHIP draws a 16x16 sprite made of 2 bytes per line for DXY0
+                let (sprite_width, sprite_lines) = if n == 0 && self.variant.supports_super_chip() {
+                    (16, 16)
+                } else {
+                    (8, n as usize)
+                };
+                let bytes_per_line = sprite_width / 8;
+                let sprite_length = sprite_lines * bytes_per_line;
+
+                self.vx[15] = 0;
+
+                // With several XO-CHIP planes selected, the sprite of each plane follows the previous one
+                let mut sprite_address = self.i;
+                for plane in 0..PLANE_COUNT {
+                    let plane_bit = 1 << plane;
+                    if self.selected_planes & plane_bit == 0 {
+                        continue;
+                    }
 
-                        if (vx_value_before >> 7) == 1 {
-                            self.vx[15] = 1;
-                        } else {
-                            self.vx[15] = 0;
+                    let sprite = self.read_ram(sprite_address, sprite_length as u16)?;
+                    sprite_address = sprite_address.wrapping_add(sprite_length as u16);
+
+                    for (column, sprite_line) in sprite.chunks(bytes_per_line).enumerate() {
+                        for row in 0..sprite_width {
+                            if (sprite_line[row / 8] & (0x80 >> (row % 8))) != 0 {
+                                let mut sprite_x = sprite_x + row;
+                                let mut sprite_y = sprite_y + column;
+
+                                if self.quirks.sprites_wrap {
+                                    sprite_x %= width;
+                                    sprite_y %= height;
+                                } else if sprite_x >= width || sprite_y >= height {
+                                    continue;
+                                }
+
+                                // Any pixel turned off is a collision
+                                if self.video_memory[sprite_x][sprite_y] & plane_bit != 0 {
+                                    self.vx[15] = 1;
+                                }
+                                self.video_memory[sprite_x][sprite_y] ^= plane_bit;
+                            }
                         }
-                        self.pc += 2;
-                    },
-                    _ => {
-                        unreachable!();
                     }
                 }
-            },
-            Some(Opcode::SneVxVy) => {
-                let first_register_index = ((opcode & 0x00F0) >> 4) as u8;
-                let second_register_index = ((opcode & 0x0F00) >> 8) as u8;
+                self.pc = self.pc.wrapping_add(2);
 
-                if self.vx[first_register_index as usize] != self.vx[second_register_index as usize] {
-                    self.pc += 4;
+                if self.quirks.display_wait {
+                    outcome = StepOutcome::WaitingForVblank;
+                }
+            },
+            Instruction::SkpVx { x } => {
+                if self.keypad.is_pressed(self.vx[x as usize]) {
+                    self.skip_next_instruction()?;
+                } else {
+                    self.pc = self.pc.wrapping_add(2);
+                }
+            },
+            Instruction::SknpVx { x } => {
+                if self.keypad.is_pressed(self.vx[x as usize]) {
+                    self.pc = self.pc.wrapping_add(2);
                 } else {
-                    self.pc += 2;
+                    self.skip_next_instruction()?;
                 }
             },
-            Some(Opcode::LdIAddr) => {
-                let value = opcode & 0x0FFF;
+            Instruction::LdILong { addr } => {
+                self.i = addr;
+                self.pc = self.pc.wrapping_add(4);
+            },
+            Instruction::Plane { planes } => {
+                self.selected_planes = planes;
+                self.pc = self.pc.wrapping_add(2);
+            },
+            Instruction::Audio => {
+                let pattern = self.read_ram(self.i, 16)?;
 
-                self.i = value;
-                self.pc += 2;
+                let mut audio_pattern = [0; 16];
+                audio_pattern.copy_from_slice(&pattern);
+                self.audio_pattern = Some(audio_pattern);
+                self.pc = self.pc.wrapping_add(2);
+            },
+            Instruction::LdVxDt { x } => {
+                self.vx[x as usize] = self.timers[0];
+                self.pc = self.pc.wrapping_add(2);
+            },
+            Instruction::LdVxK { x } => {
+                // Like on the COSMAC VIP, a key held before doesn't count and the key is only accepted once it is released again
+                outcome = StepOutcome::WaitingForKey;
+
+                while let Some(event) = self.key_events.pop_front() {
+                    match (self.waiting_key, event) {
+                        (None, KeyEvent::Pressed(key)) => self.waiting_key = Some(key),
+                        (Some(waiting_key), KeyEvent::Released(key)) if waiting_key == key => {
+                            self.vx[x as usize] = key;
+                            self.waiting_key = None;
+                            self.pc = self.pc.wrapping_add(2);
+                            outcome = StepOutcome::Executed;
+                            break;
+                        },
+                        _ => {}
+                    }
+                }
+            },
+            Instruction::LdDtVx { x } => {
+                self.timers[0] = self.vx[x as usize];
+                self.pc = self.pc.wrapping_add(2);
             },
-            Some(Opcode::DrwVxVy) => {
-                let sprite_size = (opcode & 0x000F) as u16;
-                let y_register_index = ((opcode & 0x00F0) >> 4) as u8;
-                let x_register_index = ((opcode & 0x0F00) >> 8) as u8;
-                
-                let sprite_x = (self.vx[x_register_index as usize] % 64) as usize;
-                let sprite_y = (self.vx[y_register_index as usize] % 32) as usize;
+            Instruction::LdStVx { x } => {
+                self.timers[1] = self.vx[x as usize];
+                self.pc = self.pc.wrapping_add(2);
+            },
+            Instruction::AddIVx { x } => {
+                self.i = self.i.wrapping_add(self.vx[x as usize] as u16);
+                self.pc = self.pc.wrapping_add(2);
+            },
+            Instruction::LdFVx { x } => {
+                self.i = FONT_ADDRESS + (self.vx[x as usize] & 0x0F) as u16 * 5;
+                self.pc = self.pc.wrapping_add(2);
+            },
+            Instruction::LdHfVx { x } => {
+                self.i = BIG_FONT_ADDRESS + (self.vx[x as usize] & 0x0F) as u16 * 10;
+                self.pc = self.pc.wrapping_add(2);
+            },
+            Instruction::PitchVx { x } => {
+                self.pitch = self.vx[x as usize];
+                self.pc = self.pc.wrapping_add(2);
+            },
+            Instruction::LdBVx { x } => {
+                let register_value = self.vx[x as usize];
 
-                let sprite = self.read_ram(self.i, sprite_size);
+                let bcd_values = vec![register_value / 100, register_value % 100 / 10, register_value % 10];
 
-                for column in 0..sprite.len() {
-                    for row in 0..8 {
-                        if (sprite[column] & (0x80 >> row)) != 0 {
-                            let sprite_x = sprite_x + row;
-                            let sprite_y = sprite_y + column;
+                self.write_ram(self.i, bcd_values)?;
+                self.pc = self.pc.wrapping_add(2);
+            },
+            Instruction::LdIVx { x } => {
+                self.write_ram(self.i, self.vx[..=x as usize].to_vec())?;
 
-                            if self.video_memory[sprite_x][sprite_y] ^ (sprite[column] != 0) != self.video_memory[sprite_x][sprite_y] {
-                                self.vx[15] = 1;
-                            } else {
-                                self.vx[15] = 0;
-                            }
-                            self.video_memory[sprite_x][sprite_y] ^= sprite[column] != 0;
-                        }
-                    }
+                self.increment_i_after_memory_access(x);
+                self.pc = self.pc.wrapping_add(2);
+            },
+            Instruction::LdVxI { x } => {
+                let read_memory = self.read_ram(self.i, (x + 1) as u16)?;
+
+                for i in 0..=x {
+                    self.vx[i as usize] = read_memory[i as usize];
                 }
-                self.pc += 2;
+
+                self.increment_i_after_memory_access(x);
+                self.pc = self.pc.wrapping_add(2);
+            },
+            Instruction::LdRVx { x } => {
+                self.rpl_flags[..=x as usize].copy_from_slice(&self.vx[..=x as usize]);
+                self.pc = self.pc.wrapping_add(2);
             },
-            Some(Opcode::FourteenOpcode) => {
-                match num::FromPrimitive::from_u16(opcode & 0x00FF) {
-                    Some(FourteenOpcode::SkpVx) => {
-                        let register_index = ((opcode & 0x0F00) >> 8) as u8;
-                        let value = self.vx[register_index as usize];
+            Instruction::LdVxR { x } => {
+                self.vx[..=x as usize].copy_from_slice(&self.rpl_flags[..=x as usize]);
+                self.pc = self.pc.wrapping_add(2);
+            },
+        }
 
-                        if self.scancodes.is_empty() {
-                            self.pc += 2;
-                            return;
-                        }
+        Ok(outcome)
+    }
 
-                        let mut skipped_opcode = false;
+    #[doc = "Keys held until the next call, the presses and releases are queued for LD Vx, K"]
+    pub fn set_keypad(&mut self, keypad: Keypad) {
+        self.key_events.extend(self.keypad.events_to(&keypad));
+        self.keypad = keypad;
+    }
 
-                        for scancode in self.scancodes.iter() {
-                            let keycode = self.scancode_to_value(*scancode);
+    pub fn keypad(&self) -> Keypad {
+        self.keypad
+    }
 
-                            if keycode.is_err() {
-                                continue;
-                            }
+    fn fetch_opcode(&self) -> Result<u16, EmulatorError> {
+        let bytes = self.peek_ram(self.pc, 2)?;
+        let opcode: u16 = ((bytes[0] as u16) << 8) | (bytes[1] as u16);
 
-                            if value == keycode.unwrap() {
-                                self.pc += 4;
-                                skipped_opcode = true;
-                                break;
-                            }
-                        }
+        Ok(opcode)
+    }
 
-                        if !skipped_opcode {
-                            self.pc += 2;
-                        }
-                    },
-                    Some(FourteenOpcode::SkpnVx) => {
-                        let register_index = ((opcode & 0x0F00) >> 8) as u8;
-                        let value = self.vx[register_index as usize];
-
-                        if self.scancodes.is_empty() {
-                            self.pc += 4;
-                            return;
-                        }
+    #[doc = "Decode the instruction at the program counter, the operand of `LD I, LONG` wraps around memory like the program counter"]
+    fn fetch_instruction(&self) -> Result<Instruction, EmulatorError> {
+        let opcode = self.fetch_opcode()?;
 
-                        let mut skipped_opcode = false;
+        let mut bytes = vec![(opcode >> 8) as u8, opcode as u8];
+        if let Ok(operand) = self.peek_ram(self.pc.wrapping_add(2), 2) {
+            bytes.extend(operand);
+        }
 
-                        for scancode in self.scancodes.iter() {
-                            let keycode = self.scancode_to_value(*scancode);
+        decode_at(&bytes).map_err(|_| EmulatorError::UnknownOpcode { pc: self.pc, opcode })
+    }
 
-                            if keycode.is_err() {
-                                continue;
-                            }
+    #[doc = "Skip the instruction following the current one, the 4 byte XO-CHIP long load included"]
+    fn skip_next_instruction(&mut self) -> Result<(), EmulatorError> {
+        self.pc = self.pc.wrapping_add(2);
 
-                            if value == keycode.unwrap() {
-                                self.pc += 2;
-                                skipped_opcode = true;
-                                break;
-                            }
-                        }
+        if self.variant.supports_xo_chip() && self.fetch_opcode()? == 0xF000 {
+            self.pc = self.pc.wrapping_add(4);
+        } else {
+            self.pc = self.pc.wrapping_add(2);
+        }
 
-                        if !skipped_opcode {
-                            self.pc += 4;
-                        }
-                    },
-                    _ => {
-                       panic!("Unknown opcode 0x{:x}!", opcode);
-                    }
-                }
-            },
-            Some(Opcode::FifteenOpcode) => {
-                match num::FromPrimitive::from_u16(opcode & 0x00FF) {
-                    Some(FifteenOpcode::LdVxDt) => {
-                        let register_index = ((opcode & 0x0F00) >> 8) as u8;
-
-                        self.vx[register_index as usize] = self.timers[0];
-                        self.pc += 2;
-                    },
-                    Some(FifteenOpcode::LdDtVx) => {
-                        let register_index = ((opcode & 0x0F00) >> 8) as u8;
-                        let register_value = self.vx[register_index as usize];
-
-                        self.timers[0] = register_value;
-                        self.pc += 2;
-                    },
-                    Some(FifteenOpcode::AddIVx) => {
-                        let register_index = ((opcode & 0x0F00) >> 8) as u8;
-
-                        self.i = self.i.wrapping_add(self.vx[register_index as usize] as u16);
-                        self.pc += 2;
-                    },
-                    Some(FifteenOpcode::LdFVx) => {
-                        let register_index = ((opcode & 0x0F00) >> 8) as u8;
-                        let register_value = self.vx[register_index as usize];
-
-                        self.i = 0x50 + (register_value * 5) as u16;
-                        self.pc += 2;
-                    },
-                    Some(FifteenOpcode::LdBVx) => {
-                        let register_index = ((opcode & 0x0F00) >> 8) as u8;
-                        let register_value = self.vx[register_index as usize];
-
-                        let bcd_values = vec![register_value / 100, register_value % 100 / 10, register_value % 10];
-
-                        self.write_ram(self.i, bcd_values);
-                        self.pc += 2;
-                    },
-                    Some(FifteenOpcode::LdIVx) => {
-                        let value = ((opcode & 0x0F00) >> 8) as u8;
-
-                        for i in 0..=value {
-                            self.write_ram(self.i + i as u16, vec![self.vx[i as usize]]);
-                        }
+        Ok(())
+    }
+
+    #[doc = "Values of the registers from VX to VY, in descending order if X is larger than Y"]
+    fn register_range(&self, x: u8, y: u8) -> Vec<u8> {
+        if x <= y {
+            self.vx[x as usize..=y as usize].to_vec()
+        } else {
+            self.vx[y as usize..=x as usize].iter().rev().copied().collect()
+        }
+    }
+
+    #[doc = "Jump to a specific place in memory"]
+    fn jump(&mut self, location: u16) {
+        self.pc = location;
+    }
 
-                        self.pc += 2;
-                    },
-                    Some(FifteenOpcode::LdVxI) => {
-                        let value = ((opcode & 0x0F00) >> 8) as u8;
+    #[doc = "Apply the memory increment quirk to I after FX55 / FX65 accessed registers V0 to VX"]
+    fn increment_i_after_memory_access(&mut self, x: u8) {
+        match self.quirks.memory_increment {
+            MemoryIncrement::Unchanged => {},
+            MemoryIncrement::ByX => self.i = self.i.wrapping_add(x as u16),
+            MemoryIncrement::ByXPlusOne => self.i = self.i.wrapping_add(x as u16 + 1),
+        }
+    }
 
-                        let read_memory = self.read_ram(self.i, (value + 1) as u16);
+    #[doc = "Push a value to the stack, sp is the number of values on the stack"]
+    fn push(&mut self, value: u16) -> Result<(), EmulatorError> {
+        if self.sp as usize >= self.stack.len() {
+            return Err(EmulatorError::StackOverflow { pc: self.pc });
+        }
 
-                        for i in 0..=value {
-                            self.vx[i as usize] = read_memory[i as usize];
-                        }
+        self.stack[self.sp as usize] = value;
+        self.sp += 1;
+        Ok(())
+    }
 
-                        self.pc += 2;
-                    },
-                    _ => {
-                        panic!("Unknown opcode 0x{:x}!", opcode);
-                    }
-                }
-            },
-            _ => {
-                panic!("Unknown opcode 0x{:x}!", opcode);
+    #[doc = "Pop a value from the stack"]
+    fn pop(&mut self) -> Result<u16, EmulatorError> {
+        if self.sp == 0 {
+            return Err(EmulatorError::StackUnderflow { pc: self.pc });
+        }
+
+        self.sp -= 1;
+        let result = self.stack[self.sp as usize];
+        self.stack[self.sp as usize] = 0;
+        Ok(result)
+    }
+
+    #[doc = "Clear the selected bitplanes of the vram"]
+    fn clear_screen(&mut self) {
+        let planes = self.selected_planes;
+
+        self.video_memory.iter_mut().flatten().for_each(|pixel| *pixel &= !planes);
+    }
+
+    #[doc = "Scroll the selected planes of the active screen area down (positive) or up (negative)"]
+    fn scroll_vertically(&mut self, pixels: isize) {
+        self.scroll(0, pixels);
+    }
+
+    #[doc = "Scroll the selected planes of the active screen area right (positive) or left (negative)"]
+    fn scroll_horizontally(&mut self, pixels: isize) {
+        self.scroll(pixels, 0);
+    }
+
+    fn scroll(&mut self, x_offset: isize, y_offset: isize) {
+        let (width, height) = self.resolution();
+        let planes = self.selected_planes;
+        let previous = self.video_memory;
+
+        for x in 0..width {
+            for y in 0..height {
+                let source_x = x as isize - x_offset;
+                let source_y = y as isize - y_offset;
+
+                let source = if (0..width as isize).contains(&source_x) && (0..height as isize).contains(&source_y) {
+                    previous[source_x as usize][source_y as usize]
+                } else {
+                    0
+                };
+
+                self.video_memory[x][y] = (previous[x][y] & !planes) | (source & planes);
             }
         }
+    }
+
+    #[doc = "Read the specified number of bytes from memory at an offset and log the access"]
+    fn read_ram(&mut self, offset: u16, number_of_bytes: u16) -> Result<Vec<u8>, EmulatorError> {
+        let bytes = self.peek_ram(offset, number_of_bytes)?;
 
-        self.scancodes.clear();
+        self.memory_accesses.push(MemoryAccess { kind: MemoryAccessKind::Read, address: offset, length: number_of_bytes });
+        Ok(bytes)
     }
 
-    fn scancode_to_value(&self, scancode: sdl2::keyboard::Scancode) -> Result<u8, ()> {
-        if scancode as u8 >= sdl2::keyboard::Scancode::A as u8 && scancode as u8 <= sdl2::keyboard::Scancode::F as u8 {
-            return Ok((scancode as u8) + 6);
+    #[doc = "Read the specified number of bytes from memory at an offset, without logging it"]
+    fn peek_ram(&self, offset: u16, number_of_bytes: u16) -> Result<Vec<u8>, EmulatorError> {
+        let start = offset as usize;
+        let end = start + number_of_bytes as usize;
+
+        if end > self.memory.len() {
+            return Err(EmulatorError::MemoryOutOfBounds { pc: self.pc, address: end - 1 });
         }
 
-        if scancode as u8 == sdl2::keyboard::Scancode::Num0 as u8 {
-            return Ok(0x0);
+        Ok(self.memory[start..end].to_vec())
+    }
+
+    #[doc = "Write the specified number of bytes to the memory at an offset"]
+    fn write_ram(&mut self, offset: u16, bytes: Vec<u8>) -> Result<(), EmulatorError> {
+        let start = offset as usize;
+        let end = start + bytes.len();
+
+        if end > self.memory.len() {
+            return Err(EmulatorError::MemoryOutOfBounds { pc: self.pc, address: end - 1 });
         }
 
-        if scancode as u8 >= sdl2::keyboard::Scancode::Num1 as u8 && scancode as u8 <= sdl2::keyboard::Scancode::Num9 as u8 {
-            return Ok((scancode as u8) - 29);
+        self.memory[start..end].copy_from_slice(&bytes);
+        self.memory_accesses.push(MemoryAccess { kind: MemoryAccessKind::Write, address: offset, length: bytes.len() as u16 });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn emulator(variant: Variant, quirks: Quirks) -> Emulator {
+        Emulator::new(Vec::new(), variant, quirks).unwrap()
+    }
+
+    fn xo_chip() -> Emulator {
+        emulator(Variant::XoChip, Quirks::default())
+    }
+
+    #[test]
+    fn rnd_is_masked_by_the_byte() {
+        let mut emulator = emulator(Variant::Chip8, Quirks::default());
+        emulator.set_rng_seed(1234);
+        let mut expected = XorShift::new(1234);
+
+        for mask in [0xFF, 0x0F, 0xA5, 0x00] {
+            emulator.execute(&Instruction::RndVxByte { x: 3, byte: mask }).unwrap();
+            assert_eq!(emulator.registers()[3], expected.next_u8() & mask);
         }
+        assert_eq!(emulator.pc(), 0x208);
+    }
 
-        return Err(());
+    #[test]
+    fn jp_v0_adds_the_register() {
+        let jump = Instruction::JpV0Addr { x: 2, addr: 0x234 };
+
+        let mut emulator = self::emulator(Variant::Chip8, Quirks { jump_uses_vx: false, ..Quirks::default() });
+        emulator.set_register(0, 4);
+        emulator.set_register(2, 8);
+        emulator.execute(&jump).unwrap();
+        assert_eq!(emulator.pc(), 0x238);
+
+        // BXNN jumps to XNN + VX with the quirk
+        let mut emulator = self::emulator(Variant::Chip8, Quirks { jump_uses_vx: true, ..Quirks::default() });
+        emulator.set_register(0, 4);
+        emulator.set_register(2, 8);
+        emulator.execute(&jump).unwrap();
+        assert_eq!(emulator.pc(), 0x23C);
     }
 
-    pub fn set_scancodes(&mut self, scancodes: Vec<Scancode>) {
-        self.scancodes = scancodes;
+    #[test]
+    fn ld_st_sets_the_sound_timer() {
+        let mut emulator = emulator(Variant::Chip8, Quirks::default());
+        assert!(!emulator.sound_active());
+
+        emulator.set_register(3, 30);
+        emulator.execute(&Instruction::LdStVx { x: 3 }).unwrap();
+        assert_eq!(emulator.sound_timer(), 30);
+        assert!(emulator.sound_active());
+
+        emulator.tick_timers();
+        assert_eq!(emulator.sound_timer(), 29);
     }
 
-    fn fetch_opcode(&self) -> u16 {
-        let nibble1 = self.memory[self.pc as usize];
-        let nibble2 = self.memory[(self.pc + 1) as usize];
-        let opcode: u16 = ((nibble1 as u16) << 8) | (nibble2 as u16);
+    fn keypad(keys: &[u8]) -> Keypad {
+        let mut keypad = Keypad::new();
+        keys.iter().for_each(|key| keypad.press(*key));
+        keypad
+    }
 
-        opcode
+    #[doc = "Emulator running LD V3, K after an LD V0, 0x00, one instruction per frame"]
+    fn waiting_for_key() -> Emulator {
+        Emulator::new(vec![0x60, 0x00, 0xF3, 0x0A, 0x12, 0x04], Variant::Chip8, Quirks::default()).unwrap()
     }
 
-    #[doc = "Jump to a specific place in memory"]
-    fn jump(&mut self, location: u16) {
-        self.pc = location;
+    #[doc = "Address of LD V3, K, the program counter stays there until a key is accepted"]
+    const WAITING: u16 = 0x202;
+
+    #[doc = "Run a frame with the given keys held and return the program counter"]
+    fn key_frame(emulator: &mut Emulator, keys: &[u8]) -> u16 {
+        emulator.set_keypad(keypad(keys));
+        emulator.run_frame(1).unwrap();
+        emulator.pc()
     }
 
-    #[doc = "Push a value to the stack"]
-    fn push(&mut self, value: u16) {
-        self.sp += 1;
-        self.stack[self.sp as usize] = value;
+    #[test]
+    fn ld_k_completes_on_release() {
+        let mut emulator = waiting_for_key();
+
+        assert_eq!(key_frame(&mut emulator, &[]), WAITING);
+        assert_eq!(key_frame(&mut emulator, &[]), WAITING);
+        assert_eq!(key_frame(&mut emulator, &[7]), WAITING);
+        assert_eq!(key_frame(&mut emulator, &[7]), WAITING);
+        assert_eq!(key_frame(&mut emulator, &[]), WAITING + 2);
+        assert_eq!(emulator.registers()[3], 7);
     }
 
-    #[doc = "Pop a value from the stack"]
-    fn pop(&mut self) -> u16 {
-        let result = self.stack[self.sp as usize];
-        self.stack[self.sp as usize] = 0;
-        self.sp -= 1;
-        result
+    #[test]
+    fn ld_k_accepts_a_press_and_release_in_the_same_frame() {
+        let mut emulator = waiting_for_key();
+        key_frame(&mut emulator, &[]);
+
+        emulator.set_keypad(keypad(&[0xA]));
+        assert_eq!(key_frame(&mut emulator, &[]), WAITING + 2);
+        assert_eq!(emulator.registers()[3], 0xA);
     }
 
-    #[doc = "Override the entire vram with 0's"]
-    fn clear_screen(&mut self) {
-        self.video_memory.fill([false; 32]);
+    #[test]
+    fn ld_k_ignores_a_key_held_before() {
+        let mut emulator = waiting_for_key();
+
+        // The key goes down while LD V0, 0x00 runs, before LD V3, K starts waiting
+        assert_eq!(key_frame(&mut emulator, &[4]), WAITING);
+        assert_eq!(key_frame(&mut emulator, &[4]), WAITING);
+        assert_eq!(key_frame(&mut emulator, &[]), WAITING);
+
+        assert_eq!(key_frame(&mut emulator, &[4]), WAITING);
+        assert_eq!(key_frame(&mut emulator, &[]), WAITING + 2);
+        assert_eq!(emulator.registers()[3], 4);
     }
 
-    #[doc = "Read the specified number of bytes from memory at an offset"]
-    fn read_ram(&self, offset: u16, number_of_bytes: u16) -> Vec<u8> {
-        let mut read_bytes: Vec<u8> = Vec::new();
+    #[test]
+    fn ld_k_waits_for_the_first_key_pressed() {
+        let mut emulator = waiting_for_key();
+        key_frame(&mut emulator, &[]);
 
-        for iteration in 0..number_of_bytes {
-            read_bytes.push(self.memory[(offset + iteration) as usize]);
+        // Keys pressed together are taken in key order, releasing the other one doesn't count
+        assert_eq!(key_frame(&mut emulator, &[2, 9]), WAITING);
+        assert_eq!(key_frame(&mut emulator, &[2]), WAITING);
+        assert_eq!(key_frame(&mut emulator, &[]), WAITING + 2);
+        assert_eq!(emulator.registers()[3], 2);
+    }
+
+    #[doc = "Execute instructions from the program address, returning the emulator"]
+    fn run(mut emulator: Emulator, instructions: &[Instruction]) -> Emulator {
+        for instruction in instructions {
+            emulator.execute(instruction).unwrap();
         }
+        emulator
+    }
 
-        read_bytes
+    fn with_registers(quirks: Quirks, registers: &[(u8, u8)]) -> Emulator {
+        let mut emulator = emulator(Variant::Chip8, quirks);
+        for (x, value) in registers {
+            emulator.set_register(*x, *value);
+        }
+        emulator
     }
 
-    #[doc = "Write the specified number of bytes to the memory at an offset"]
-    fn write_ram(&mut self, offset: u16, bytes: Vec<u8>) {
-        for (i, byte) in bytes.iter().enumerate() {
-            self.memory[offset as usize + i] = *byte;
+    #[test]
+    fn quirk_shift_uses_vy() {
+        let shifts = [Instruction::ShrVxVy { x: 1, y: 2 }, Instruction::ShlVxVy { x: 3, y: 4 }];
+        let registers = [(1, 0x01), (2, 0x04), (3, 0x80), (4, 0x21)];
+
+        // VY is shifted into VX
+        let vip = run(with_registers(Quirks::cosmac_vip(), &registers), &shifts);
+        assert_eq!(vip.registers()[1], 0x02);
+        assert_eq!(vip.registers()[3], 0x42);
+        assert_eq!(vip.registers()[15], 0);
+
+        // VX is shifted in place
+        let schip = run(with_registers(Quirks::super_chip(), &registers), &shifts);
+        assert_eq!(schip.registers()[1], 0x00);
+        assert_eq!(schip.registers()[3], 0x00);
+        assert_eq!(schip.registers()[15], 1);
+    }
+
+    #[test]
+    fn quirk_memory_increment() {
+        let i_after = |quirks: Quirks, instruction: Instruction| {
+            let mut emulator = emulator(Variant::Chip8, quirks);
+            emulator.set_i(0x300);
+            run(emulator, &[instruction]).i()
+        };
+
+        for instruction in [Instruction::LdIVx { x: 2 }, Instruction::LdVxI { x: 2 }] {
+            assert_eq!(i_after(Quirks::cosmac_vip(), instruction), 0x303);
+            assert_eq!(i_after(Quirks::chip48(), instruction), 0x302);
+            assert_eq!(i_after(Quirks::super_chip(), instruction), 0x300);
+        }
+    }
+
+    #[test]
+    fn quirk_logic_resets_vf() {
+        for instruction in [Instruction::OrVxVy { x: 1, y: 2 }, Instruction::AndVxVy { x: 1, y: 2 }, Instruction::XorVxVy { x: 1, y: 2 }] {
+            let registers = [(1, 0x0C), (2, 0x0A), (15, 5)];
+
+            assert_eq!(run(with_registers(Quirks::cosmac_vip(), &registers), &[instruction]).registers()[15], 0);
+            assert_eq!(run(with_registers(Quirks::modern(), &registers), &[instruction]).registers()[15], 5);
+        }
+    }
+
+    #[test]
+    fn quirk_jump_uses_vx() {
+        let registers = [(0, 0x10), (3, 0x20)];
+        let jump = [Instruction::JpV0Addr { x: 3, addr: 0x345 }];
+
+        assert_eq!(run(with_registers(Quirks::cosmac_vip(), &registers), &jump).pc(), 0x355);
+        assert_eq!(run(with_registers(Quirks::super_chip(), &registers), &jump).pc(), 0x365);
+    }
+
+    #[test]
+    fn quirk_sprites_wrap() {
+        // The top line of the 0 glyph is 4 pixels wide, drawn 2 pixels before the right and bottom edges
+        let draw = |quirks: Quirks| {
+            let mut emulator = with_registers(quirks, &[(0, 62), (1, 30)]);
+            emulator.set_i(FONT_ADDRESS);
+            run(emulator, &[Instruction::DrwVxVyN { x: 0, y: 1, n: 5 }])
+        };
+
+        let modern = draw(Quirks::modern());
+        assert_eq!(modern.video_memory()[63][30], 1);
+        assert_eq!(modern.video_memory()[0][30], 1);
+        assert_eq!(modern.video_memory()[62][0], 1);
+
+        let vip = draw(Quirks::cosmac_vip());
+        assert_eq!(vip.video_memory()[63][30], 1);
+        assert_eq!(vip.video_memory()[0][30], 0);
+        assert_eq!(vip.video_memory()[62][0], 0);
+    }
+
+    #[test]
+    fn quirk_display_wait() {
+        // DRW V0, V0, 1 / ADD V5, 1 / ADD V5, 1
+        let rom = vec![0xD0, 0x01, 0x75, 0x01, 0x75, 0x01];
+
+        let mut vip = Emulator::new(rom.clone(), Variant::Chip8, Quirks::cosmac_vip()).unwrap();
+        vip.run_frame(3).unwrap();
+        assert_eq!((vip.pc(), vip.registers()[5]), (0x202, 0));
+
+        let mut modern = Emulator::new(rom, Variant::Chip8, Quirks::modern()).unwrap();
+        modern.run_frame(3).unwrap();
+        assert_eq!((modern.pc(), modern.registers()[5]), (0x206, 2));
+    }
+
+    #[doc = "SUPER-CHIP emulator with a single pixel drawn at each of the given positions, in hi-res if asked"]
+    fn with_pixels(hires: bool, pixels: &[(u8, u8)]) -> Emulator {
+        let mut emulator = emulator(Variant::SuperChip, Quirks::super_chip());
+        emulator.write_memory(0x300, &[0x80]).unwrap();
+        emulator.set_i(0x300);
+        if hires {
+            emulator.execute(&Instruction::High).unwrap();
+        }
+
+        for (x, y) in pixels {
+            emulator.set_register(0, *x);
+            emulator.set_register(1, *y);
+            emulator.execute(&Instruction::DrwVxVyN { x: 0, y: 1, n: 1 }).unwrap();
+        }
+        emulator
+    }
+
+    #[doc = "Positions of the pixels turned on in the active screen area"]
+    fn lit_pixels(emulator: &Emulator) -> Vec<(usize, usize)> {
+        let (width, height) = emulator.resolution();
+        (0..height).flat_map(|y| (0..width).map(move |x| (x, y))).filter(|(x, y)| emulator.video_memory()[*x][*y] != 0).collect()
+    }
+
+    #[test]
+    fn scroll_down() {
+        // Pixels scrolled past the bottom are lost
+        let lores = run(with_pixels(false, &[(5, 0), (6, 31)]), &[Instruction::ScdN { n: 3 }]);
+        assert_eq!(lit_pixels(&lores), [(5, 3)]);
+
+        let hires = run(with_pixels(true, &[(5, 0), (6, 31), (7, 63)]), &[Instruction::ScdN { n: 3 }]);
+        assert_eq!(lit_pixels(&hires), [(5, 3), (6, 34)]);
+    }
+
+    #[test]
+    fn scroll_right_and_left() {
+        let lores = run(with_pixels(false, &[(0, 1), (61, 2)]), &[Instruction::Scr]);
+        assert_eq!(lit_pixels(&lores), [(4, 1)]);
+
+        let lores = run(with_pixels(false, &[(2, 1), (10, 2)]), &[Instruction::Scl]);
+        assert_eq!(lit_pixels(&lores), [(6, 2)]);
+
+        let hires = run(with_pixels(true, &[(0, 1), (100, 40), (125, 50)]), &[Instruction::Scr]);
+        assert_eq!(lit_pixels(&hires), [(4, 1), (104, 40)]);
+
+        let hires = run(with_pixels(true, &[(2, 1), (127, 63)]), &[Instruction::Scl]);
+        assert_eq!(lit_pixels(&hires), [(123, 63)]);
+    }
+
+    #[test]
+    fn large_sprite_collides() {
+        let mut emulator = with_pixels(true, &[]);
+        emulator.write_memory(0x300, &[0xFF; 32]).unwrap();
+        emulator.set_register(0, 8);
+        emulator.set_register(1, 4);
+        let draw = Instruction::DrwVxVyN { x: 0, y: 1, n: 0 };
+
+        emulator.execute(&draw).unwrap();
+        let pixels = lit_pixels(&emulator);
+        assert_eq!(pixels.len(), 16 * 16);
+        assert_eq!((pixels[0], pixels[255]), ((8, 4), (23, 19)));
+        assert_eq!(emulator.registers()[15], 0);
+
+        // Drawing it again erases it
+        emulator.execute(&draw).unwrap();
+        assert!(lit_pixels(&emulator).is_empty());
+        assert_eq!(emulator.registers()[15], 1);
+    }
+
+    #[test]
+    fn resolution_switches_clear_the_screen() {
+        let mut emulator = with_pixels(false, &[(1, 1)]);
+        assert_eq!(emulator.resolution(), (64, 32));
+
+        emulator.execute(&Instruction::High).unwrap();
+        assert_eq!(emulator.resolution(), (128, 64));
+        assert!(lit_pixels(&emulator).is_empty());
+
+        let mut emulator = with_pixels(true, &[(100, 50)]);
+        emulator.execute(&Instruction::Low).unwrap();
+        assert_eq!(emulator.resolution(), (64, 32));
+        assert_eq!(emulator.video_memory()[100][50], 0);
+    }
+
+    fn xo_chip_sprite(planes: u8, sprite: &[u8]) -> Emulator {
+        let mut emulator = xo_chip();
+        emulator.write_memory(0x300, sprite).unwrap();
+        emulator.set_i(0x300);
+        run(emulator, &[Instruction::Plane { planes }])
+    }
+
+    #[test]
+    fn plane_selects_the_drawn_and_cleared_planes() {
+        let draw = Instruction::DrwVxVyN { x: 0, y: 1, n: 1 };
+
+        let second = run(xo_chip_sprite(2, &[0x80]), &[draw]);
+        assert_eq!(second.video_memory()[0][0], 0b10);
+
+        let none = run(xo_chip_sprite(0, &[0x80]), &[draw]);
+        assert_eq!(lit_pixels(&none), []);
+
+        // Clearing only touches the selected planes
+        let cleared = run(xo_chip_sprite(3, &[0x80, 0x80]), &[draw, Instruction::Plane { planes: 1 }, Instruction::Cls]);
+        assert_eq!(cleared.video_memory()[0][0], 0b10);
+    }
+
+    #[test]
+    fn sprites_are_drawn_into_both_planes() {
+        // The sprite of the second plane follows the one of the first
+        let draw = Instruction::DrwVxVyN { x: 0, y: 1, n: 1 };
+        let emulator = run(xo_chip_sprite(3, &[0xC0, 0x60]), &[draw]);
+        assert_eq!(emulator.video_memory()[0][0], 0b01);
+        assert_eq!(emulator.video_memory()[1][0], 0b11);
+        assert_eq!(emulator.video_memory()[2][0], 0b10);
+        assert_eq!(emulator.registers()[0xF], 0);
+
+        let emulator = run(emulator, &[draw]);
+        assert_eq!(lit_pixels(&emulator), []);
+        assert_eq!(emulator.registers()[0xF], 1);
+    }
+
+    #[test]
+    fn register_ranges_are_stored_in_order() {
+        let mut emulator = xo_chip();
+        emulator.set_i(0x300);
+        for (x, value) in [(2, 1), (3, 2), (4, 3)] {
+            emulator.set_register(x, value);
         }
+
+        let emulator = run(emulator, &[Instruction::SaveVxVy { x: 2, y: 4 }]);
+        assert_eq!(emulator.memory()[0x300..0x303], [1, 2, 3]);
+        assert_eq!(emulator.i(), 0x300);
+
+        let emulator = run(emulator, &[Instruction::SaveVxVy { x: 4, y: 2 }]);
+        assert_eq!(emulator.memory()[0x300..0x303], [3, 2, 1]);
+        assert_eq!(emulator.i(), 0x300);
+    }
+
+    #[test]
+    fn register_ranges_are_loaded_in_order() {
+        let mut emulator = xo_chip();
+        emulator.write_memory(0x300, &[9, 8, 7]).unwrap();
+        emulator.set_i(0x300);
+
+        let emulator = run(emulator, &[Instruction::LoadVxVy { x: 5, y: 7 }]);
+        assert_eq!(emulator.registers()[5..8], [9, 8, 7]);
+        assert_eq!(emulator.i(), 0x300);
+
+        let emulator = run(emulator, &[Instruction::LoadVxVy { x: 7, y: 5 }]);
+        assert_eq!(emulator.registers()[5..8], [7, 8, 9]);
+        assert_eq!(emulator.i(), 0x300);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn long_load_sets_i_and_is_skipped_whole() {
+        let mut emulator = Emulator::new(vec![0xF0, 0x00, 0xAB, 0xCD, 0x30, 0x00, 0xF0, 0x00, 0x12, 0x34], Variant::XoChip, Quirks::default()).unwrap();
+        emulator.next_cycle().unwrap();
+        assert_eq!(emulator.i(), 0xABCD);
+        assert_eq!(emulator.pc(), 0x204);
+
+        // Skips jump over the 4 bytes of F000 NNNN
+        emulator.next_cycle().unwrap();
+        assert_eq!(emulator.pc(), 0x20A);
+        assert_eq!(emulator.i(), 0xABCD);
+    }
+
+    #[test]
+    fn audio_loads_the_pattern_and_pitch() {
+        let pattern: [u8; 16] = std::array::from_fn(|byte| byte as u8 * 0x11);
+        let mut emulator = xo_chip();
+        emulator.write_memory(0x300, &pattern).unwrap();
+        emulator.set_i(0x300);
+        assert_eq!(emulator.audio_pattern(), None);
+        assert_eq!(emulator.audio_playback_rate(), 4000.0);
+
+        emulator.set_register(2, 112);
+        let emulator = run(emulator, &[Instruction::Audio, Instruction::PitchVx { x: 2 }]);
+        assert_eq!(emulator.audio_pattern(), Some(&pattern));
+        assert_eq!(emulator.i(), 0x300);
+        assert_eq!(emulator.audio_playback_rate(), 8000.0);
+    }
+
+    #[test]
+    fn register_store_past_the_end_of_memory_fails() {
+        let mut emulator = xo_chip();
+        emulator.set_register(0, 0xAA);
+        emulator.set_register(1, 0xBB);
+        emulator.set_i(0xFFFF);
+        let start_of_memory = emulator.memory()[..2].to_vec();
+
+        assert_eq!(emulator.execute(&Instruction::LdIVx { x: 1 }), Err(EmulatorError::MemoryOutOfBounds { pc: 0x200, address: 0x10000 }));
+        assert_eq!(emulator.memory()[0xFFFF], 0);
+        assert_eq!(&emulator.memory()[..2], start_of_memory);
+        assert_eq!(emulator.i(), 0xFFFF);
+
+        // The last byte of memory can still be stored
+        assert_eq!(emulator.execute(&Instruction::LdIVx { x: 0 }), Ok(StepOutcome::Executed));
+        assert_eq!(emulator.memory()[0xFFFF], 0xAA);
+    }
+
+    #[test]
+    fn register_load_past_the_end_of_memory_fails() {
+        let mut emulator = xo_chip();
+        emulator.write_memory(0xFFFF, &[0xCC]).unwrap();
+        emulator.set_i(0xFFFF);
+
+        assert_eq!(emulator.execute(&Instruction::LdVxI { x: 1 }), Err(EmulatorError::MemoryOutOfBounds { pc: 0x200, address: 0x10000 }));
+        assert_eq!(emulator.registers()[..2], [0, 0]);
+        assert_eq!(emulator.i(), 0xFFFF);
+
+        assert_eq!(emulator.execute(&Instruction::LdVxI { x: 0 }), Ok(StepOutcome::Executed));
+        assert_eq!(emulator.registers()[0], 0xCC);
+    }
+
+    #[doc = "Execute an instruction on the given register values and return VX and VF"]
+    fn arithmetic(instruction: Instruction, registers: &[(u8, u8)]) -> (u8, u8) {
+        let mut emulator = emulator(Variant::Chip8, Quirks::default());
+        for (x, value) in registers {
+            emulator.set_register(*x, *value);
+        }
+
+        let x = match instruction {
+            Instruction::SubVxVy { x, .. } | Instruction::SubnVxVy { x, .. } => x,
+            _ => unreachable!(),
+        };
+        emulator.execute(&instruction).unwrap();
+
+        (emulator.registers()[x as usize], emulator.registers()[15])
+    }
+
+    #[test]
+    fn sub_sets_vf_without_borrow() {
+        let sub = Instruction::SubVxVy { x: 1, y: 0 };
+
+        assert_eq!(arithmetic(sub, &[(1, 5), (0, 3)]), (2, 1));
+        assert_eq!(arithmetic(sub, &[(1, 5), (0, 0)]), (5, 1));
+        assert_eq!(arithmetic(sub, &[(1, 4), (0, 4)]), (0, 1));
+        assert_eq!(arithmetic(sub, &[(1, 3), (0, 5)]), (0xFE, 0));
+
+        // The flag overwrites the result when X is F
+        assert_eq!(arithmetic(Instruction::SubVxVy { x: 0xF, y: 0 }, &[(0xF, 1), (0, 2)]), (0, 0));
+        assert_eq!(arithmetic(Instruction::SubVxVy { x: 0xF, y: 0 }, &[(0xF, 9), (0, 2)]), (1, 1));
+    }
+
+    #[test]
+    fn subn_sets_vf_without_borrow() {
+        let subn = Instruction::SubnVxVy { x: 1, y: 0 };
+
+        assert_eq!(arithmetic(subn, &[(1, 3), (0, 5)]), (2, 1));
+        assert_eq!(arithmetic(subn, &[(1, 0), (0, 0)]), (0, 1));
+        assert_eq!(arithmetic(subn, &[(1, 0), (0, 7)]), (7, 1));
+        assert_eq!(arithmetic(subn, &[(1, 5), (0, 3)]), (0xFE, 0));
+        assert_eq!(arithmetic(Instruction::SubnVxVy { x: 0xF, y: 0 }, &[(0xF, 2), (0, 1)]), (0, 0));
+    }
+}
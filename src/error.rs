@@ -0,0 +1,40 @@
+use std::fmt;
+
+#[doc = "Result of successfully executing a single instruction"]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepOutcome {
+    Executed,
+    #[doc = "LD Vx, K is blocking until a key is pressed and released"]
+    WaitingForKey,
+    #[doc = "A sprite was drawn and the display wait quirk asks to stop until the next frame"]
+    WaitingForVblank,
+    #[doc = "The program ran the SUPER-CHIP exit instruction"]
+    Exit,
+}
+
+#[doc = "Why the emulator can't run: a rom that can't be loaded, or a fatal condition hit by the emulated program"]
+#[doc = ""]
+#[doc = "Execution can't continue after any of them"]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EmulatorError {
+    UnknownOpcode { pc: u16, opcode: u16 },
+    StackOverflow { pc: u16 },
+    StackUnderflow { pc: u16 },
+    MemoryOutOfBounds { pc: u16, address: usize },
+    #[doc = "The rom doesn't fit in the memory of the variant after 0x200, only returned by Emulator::new()"]
+    RomTooLarge { size: usize, max: usize },
+}
+
+impl fmt::Display for EmulatorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EmulatorError::UnknownOpcode { pc, opcode } => write!(f, "unknown opcode 0x{:04X} at 0x{:03X}", opcode, pc),
+            EmulatorError::StackOverflow { pc } => write!(f, "stack overflow at 0x{:03X}", pc),
+            EmulatorError::StackUnderflow { pc } => write!(f, "stack underflow at 0x{:03X}", pc),
+            EmulatorError::MemoryOutOfBounds { pc, address } => write!(f, "memory access out of bounds (0x{:X}) at 0x{:03X}", address, pc),
+            EmulatorError::RomTooLarge { size, max } => write!(f, "rom of {} bytes is too large, at most {} bytes fit in memory", size, max),
+        }
+    }
+}
+
+impl std::error::Error for EmulatorError {}
@@ -0,0 +1,47 @@
+use std::time::{Duration, Instant};
+
+#[doc = "Upper bound of frames run in one go, so a stalled host doesn't try to catch up forever"]
+const MAX_FRAMES_PER_UPDATE: u32 = 5;
+
+pub struct FrameScheduler {
+    frame_duration: Duration,
+    last_update: Instant,
+    accumulator: Duration,
+}
+
+impl FrameScheduler {
+    pub fn new(frames_per_second: u32) -> Self {
+        Self {
+            frame_duration: Duration::from_secs(1) / frames_per_second,
+            last_update: Instant::now(),
+            accumulator: Duration::ZERO,
+        }
+    }
+
+    #[doc = "Sleep until at least one frame is due and return the number of frames to run"]
+    pub fn wait_for_frames(&mut self) -> u32 {
+        loop {
+            let now = Instant::now();
+            self.accumulator += now - self.last_update;
+            self.last_update = now;
+
+            if self.accumulator >= self.frame_duration {
+                break;
+            }
+
+            std::thread::sleep(self.frame_duration - self.accumulator);
+        }
+
+        let mut frames = 0;
+        while self.accumulator >= self.frame_duration {
+            self.accumulator -= self.frame_duration;
+            frames += 1;
+        }
+
+        if frames > MAX_FRAMES_PER_UPDATE {
+            frames = MAX_FRAMES_PER_UPDATE;
+        }
+
+        frames
+    }
+}
@@ -0,0 +1,287 @@
+use std::path::Path;
+
+use nauka::{audio::Beeper, movie::Movie, rewind::RewindBuffer, Emulator, EmulatorError, Keypad, StepOutcome};
+use sdl2::{keyboard::Scancode, EventPump};
+
+use crate::audio_device::SdlAudioSink;
+use crate::controllers::Controllers;
+use crate::frame_calculator::FrameCalculator;
+use crate::frame_scheduler::FrameScheduler;
+use crate::input::InputState;
+use crate::keymap::Keymap;
+use crate::renderer::Renderer;
+use crate::save_slots::SaveSlots;
+use crate::AppConfiguration;
+
+#[doc = "Rate of the delay/sound timers and of emulated frames"]
+const FRAMES_PER_SECOND: u32 = 60;
+const AUDIO_SAMPLE_RATE: i32 = 44100;
+
+#[derive(Debug, PartialEq)]
+pub enum AppStatus {
+    Continue,
+    Crashed(EmulatorError),
+    #[doc = "A save state was loaded, which also recovers from a crash"]
+    StateLoaded,
+    #[doc = "The program ran the exit instruction"]
+    Exit,
+    #[doc = "The window was closed, the current frame didn't run"]
+    Quit
+}
+
+#[doc = "Run a rom in an SDL window with audio and keyboard input"]
+pub fn run(configuration: AppConfiguration) {
+    let sdl = sdl2::init().expect("Failed to init SDL!");
+    let sdl_video = sdl.video().expect("Failed to init SDL Video!");
+    let sdl_audio = sdl.audio().expect("Failed to init SDL Audio!");
+
+    let mut event_pump = sdl.event_pump().expect("Failed to init SDL Event Pump!");
+    let controllers = Controllers::new(&sdl).expect("Failed to init SDL Game Controller!");
+
+    let window = sdl_video.window("CHIP8 Emulator", configuration.width, configuration.height)
+    .allow_highdpi()
+    .resizable()
+    .build()
+    .expect("Failed to init SDL Window!");
+
+    let mut window_canvas = window.into_canvas();
+
+    if configuration.hardware_canvas {
+        window_canvas = window_canvas.accelerated();
+    } else {
+        window_canvas = window_canvas.software();
+    }
+
+    let mut window_canvas = window_canvas.build()
+    .expect("Failed to create window canvas!");
+
+    let texture_creator = window_canvas.texture_creator();
+    let mut renderer = Renderer::new(&texture_creator, configuration.filter, configuration.integer_scaling, configuration.theme, crate::palette(&configuration)).expect("Failed to create the screen texture!");
+
+    let replay = configuration.replay.as_ref().map(|path| crate::load_movie(Path::new(path)));
+
+    // A replay has to run with the settings it was recorded with
+    let (variant, quirks, instructions_per_frame) = match &replay {
+        Some(movie) => (movie.variant, movie.quirks, movie.instructions_per_frame),
+        None => (configuration.variant, configuration.quirks.quirks(), configuration.ipf)
+    };
+
+    let mut emulator = crate::create_emulator(&configuration, variant, quirks);
+
+    if let Some(seed) = configuration.seed {
+        emulator.set_rng_seed(seed);
+    }
+
+    emulator.set_tracer(crate::create_tracer(&configuration));
+
+    if let Some(movie) = &replay {
+        crate::start_replay(movie, &mut emulator);
+    }
+
+    let keymap = match Keymap::load(configuration.keymap.as_deref().map(Path::new), Path::new(&configuration.rom)) {
+        Ok(keymap) => keymap,
+        Err(error) => {
+            eprintln!("error: invalid keymap, {}", error);
+            std::process::exit(1);
+        }
+    };
+
+    let mut input = InputState::new(keymap, controllers);
+
+    let mut recording = configuration.record.as_ref().map(|_| Movie::new(variant, quirks, instructions_per_frame, emulator.rom_hash(), emulator.rng_seed()));
+    let movie_active = replay.is_some() || recording.is_some();
+
+    // Movies always start from cleared RPL flags, otherwise they wouldn't replay the same
+    let rpl_flags_path = Path::new(&configuration.rom).with_extension("rpl");
+    if variant.supports_super_chip() && !movie_active {
+        load_rpl_flags(&rpl_flags_path, &mut emulator);
+    }
+
+    let audio_sink = SdlAudioSink::new(&sdl_audio, AUDIO_SAMPLE_RATE, FRAMES_PER_SECOND).expect("Failed to open SDL Audio device!");
+    let mut beeper = Beeper::new(configuration.waveform, configuration.beep_frequency, configuration.volume, FRAMES_PER_SECOND, audio_sink);
+
+    // Jumping around in time would make movies desync
+    let mut save_slots = SaveSlots::new(Path::new(&configuration.rom));
+    let mut rewind_buffer = RewindBuffer::new((configuration.rewind_seconds * FRAMES_PER_SECOND) as usize);
+    if movie_active {
+        save_slots.disable_loading();
+        rewind_buffer = RewindBuffer::new(0);
+    }
+
+    let mut frame_calculator = FrameCalculator::new();
+    let mut frame_scheduler = FrameScheduler::new(FRAMES_PER_SECOND);
+
+    // Once the emulated program crashes, it stays on screen until the window is closed or a state is loaded
+    let mut crash: Option<EmulatorError> = None;
+    let mut frame = 0;
+
+    'run_loop: loop {
+        for _ in 0..frame_scheduler.wait_for_frames() {
+            // Movies store a keypad per frame, so while one is active keys only change between frames
+            let replayed_keypad = replay.as_ref().and_then(|movie| movie.frames.get(frame)).map(|mask| Keypad::from_mask(*mask));
+            input.set_live(replayed_keypad.is_none() && recording.is_none());
+
+            match replayed_keypad {
+                Some(keypad) => emulator.set_keypad(keypad),
+                None if recording.is_some() => emulator.set_keypad(input.keypad()),
+                None => {}
+            }
+
+            // Holding Backspace steps back one frame per frame, also recovering from a crash
+            let rewinding = rewind_buffer.capacity() > 0 && event_pump.keyboard_state().is_scancode_pressed(Scancode::Backspace);
+            if rewinding {
+                if let Some(state) = rewind_buffer.rewind() {
+                    emulator.load_state(&state).expect("Failed to restore a rewind snapshot!");
+
+                    if crash.take().is_some() {
+                        window_canvas.window_mut().set_title("CHIP8 Emulator").expect("Failed to set SDL Window title!");
+                    }
+                }
+            }
+
+            let keypad = emulator.keypad();
+            let status = update(&mut event_pump, &mut input, &mut emulator, &mut save_slots, &mut renderer, instructions_per_frame, crash.is_none() && !rewinding);
+
+            if status != AppStatus::Quit {
+                if let Some(movie) = recording.as_mut() {
+                    movie.frames.push(keypad.mask());
+                }
+                frame += 1;
+            }
+
+            match status {
+                AppStatus::Continue => {},
+                AppStatus::Crashed(error) => {
+                    eprintln!("Emulator crashed: {}", error);
+                    window_canvas.window_mut().set_title(&format!("CHIP8 Emulator - crashed: {}", error)).expect("Failed to set SDL Window title!");
+                    crash = Some(error);
+                },
+                AppStatus::StateLoaded => {
+                    window_canvas.window_mut().set_title("CHIP8 Emulator").expect("Failed to set SDL Window title!");
+                    crash = None;
+                },
+                AppStatus::Exit | AppStatus::Quit => {
+                    break 'run_loop;
+                }
+            }
+
+            if replay.as_ref().is_some_and(|movie| frame == movie.frames.len()) {
+                if configuration.verify {
+                    break 'run_loop;
+                }
+
+                println!("Replay finished");
+            }
+
+            if crash.is_none() {
+                beeper.update(&emulator);
+
+                if !rewinding {
+                    rewind_buffer.push(emulator.save_state());
+                }
+            }
+        }
+        renderer.render(&mut window_canvas, &emulator, crash.is_some()).expect("Failed to render the screen!");
+
+        if configuration.frame_calculator {
+            frame_calculator.tick();
+            println!("{}", frame_calculator.fps());
+        }
+    }
+
+    if variant.supports_super_chip() && !movie_active {
+        save_rpl_flags(&rpl_flags_path, &emulator);
+    }
+
+    if let (Some(path), Some(mut movie)) = (&configuration.record, recording) {
+        movie.screen_hash = Some(emulator.screen_hash());
+        std::fs::write(path, movie.to_bytes()).expect("Failed to write the movie!");
+    }
+
+    // Flushes the trace before verify_replay() can exit
+    emulator.set_tracer(None);
+
+    if let (true, Some(movie)) = (configuration.verify, &replay) {
+        crate::verify_replay(movie, &emulator, frame);
+    }
+}
+#[doc = "Restore the SUPER-CHIP RPL flags saved by a previous run, if any"]
+fn load_rpl_flags(path: &Path, emulator: &mut Emulator) {
+    if let Ok(bytes) = std::fs::read(path) {
+        let mut rpl_flags = [0; 16];
+        let length = bytes.len().min(rpl_flags.len());
+
+        rpl_flags[..length].copy_from_slice(&bytes[..length]);
+        emulator.set_rpl_flags(rpl_flags);
+    }
+}
+
+fn save_rpl_flags(path: &Path, emulator: &Emulator) {
+    if let Err(error) = std::fs::write(path, emulator.rpl_flags()) {
+        eprintln!("Failed to save the RPL flags to {}: {}", path.display(), error);
+    }
+}
+
+#[doc = "Handle the pending events and run a frame"]
+fn update(event_pump: &mut EventPump, input: &mut InputState, emulator: &mut Emulator, save_slots: &mut SaveSlots, renderer: &mut Renderer, instructions_per_frame: u32, running: bool) -> AppStatus {
+    let mut state_loaded = false;
+
+    for event in event_pump.poll_iter() {
+        if input.handle_event(&event) && input.is_live() {
+            emulator.set_keypad(input.keypad());
+        }
+
+        match event {
+            sdl2::event::Event::Quit { .. } => return AppStatus::Quit,
+            sdl2::event::Event::KeyDown { scancode: Some(scancode), repeat: false, .. } => {
+                state_loaded |= handle_hotkey(scancode, emulator, save_slots, renderer);
+            },
+            _ => {}
+        }
+    }
+
+    if state_loaded {
+        return AppStatus::StateLoaded;
+    }
+
+    if running {
+        match emulator.run_frame(instructions_per_frame) {
+            Ok(StepOutcome::Exit) => return AppStatus::Exit,
+            Ok(_) => {},
+            Err(error) => return AppStatus::Crashed(error),
+        }
+    }
+
+    AppStatus::Continue
+}
+
+#[doc = "F5 saves to the current slot, F9 loads from it and F6 / F7 select the previous / next slot, F2 switches to the next theme"]
+#[doc = ""]
+#[doc = "Returns whether a save state was loaded"]
+fn handle_hotkey(scancode: Scancode, emulator: &mut Emulator, save_slots: &mut SaveSlots, renderer: &mut Renderer) -> bool {
+    match scancode {
+        Scancode::F2 => println!("Theme {}", renderer.next_theme().name()),
+        Scancode::F5 => match save_slots.save(emulator) {
+            Ok(()) => println!("Saved state to slot {}", save_slots.slot()),
+            Err(error) => eprintln!("Failed to save the state to {}: {}", save_slots.path().display(), error),
+        },
+        Scancode::F9 => match save_slots.load(emulator) {
+            Ok(()) => {
+                println!("Loaded state from slot {}", save_slots.slot());
+                return true;
+            },
+            Err(error) => eprintln!("Failed to load the state from {}: {}", save_slots.path().display(), error),
+        },
+        Scancode::F6 => {
+            save_slots.previous();
+            println!("Selected save slot {}", save_slots.slot());
+        },
+        Scancode::F7 => {
+            save_slots.next();
+            println!("Selected save slot {}", save_slots.slot());
+        },
+        _ => {}
+    }
+
+    false
+}
@@ -0,0 +1,500 @@
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+
+use crate::debugger::{DebugStop, Debugger};
+use crate::emulator::Emulator;
+use crate::error::EmulatorError;
+
+#[doc = "Instructions run between two checks for an interrupt from the debugger front-end"]
+const CONTINUE_CHUNK: u32 = 10_000;
+
+#[doc = "Register numbers of the g / p packets: V0-VF are 0-15 (8 bits), then I (16 bits), PC (16 bits) and SP (8 bits, read-only)"]
+const REGISTER_I: usize = 16;
+const REGISTER_PC: usize = 17;
+const REGISTER_SP: usize = 18;
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.nauka.chip8">
+    <reg name="v0" bitsize="8"/><reg name="v1" bitsize="8"/><reg name="v2" bitsize="8"/><reg name="v3" bitsize="8"/>
+    <reg name="v4" bitsize="8"/><reg name="v5" bitsize="8"/><reg name="v6" bitsize="8"/><reg name="v7" bitsize="8"/>
+    <reg name="v8" bitsize="8"/><reg name="v9" bitsize="8"/><reg name="va" bitsize="8"/><reg name="vb" bitsize="8"/>
+    <reg name="vc" bitsize="8"/><reg name="vd" bitsize="8"/><reg name="ve" bitsize="8"/><reg name="vf" bitsize="8"/>
+    <reg name="i" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="sp" bitsize="8"/>
+  </feature>
+</target>
+"#;
+
+#[doc = "Serve a GDB remote serial protocol session on an accepted connection, until the client kills, detaches or disconnects"]
+pub fn serve(stream: TcpStream, emulator: &mut Emulator, instructions_per_frame: u32) -> io::Result<()> {
+    match run_session(stream, emulator, instructions_per_frame) {
+        Err(error) if is_disconnect(&error) => Ok(()),
+        result => result,
+    }
+}
+
+fn is_disconnect(error: &io::Error) -> bool {
+    matches!(error.kind(), io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionAborted | io::ErrorKind::BrokenPipe | io::ErrorKind::UnexpectedEof)
+}
+
+fn run_session(stream: TcpStream, emulator: &mut Emulator, instructions_per_frame: u32) -> io::Result<()> {
+    let mut connection = Connection { stream, buffer: Vec::new() };
+    let mut debugger = Debugger::new(instructions_per_frame);
+
+    while let Some(packet) = connection.read_packet()? {
+        let reply = match packet.as_bytes().first() {
+            Some(b'?') => "S05".to_string(),
+            Some(b'g') => hex(&registers(emulator)),
+            Some(b'G') => match from_hex(&packet[1..]) {
+                Some(bytes) if write_registers(emulator, &bytes) => "OK".to_string(),
+                _ => "E01".to_string(),
+            },
+            Some(b'p') => match usize::from_str_radix(&packet[1..], 16).ok().and_then(|number| register(emulator, number)) {
+                Some(bytes) => hex(&bytes),
+                None => "E01".to_string(),
+            },
+            Some(b'P') => match write_register_packet(emulator, &packet[1..]) {
+                Some(()) => "OK".to_string(),
+                None => "E01".to_string(),
+            },
+            Some(b'm') => match parse_address_length(&packet[1..]) {
+                Some((address, length)) => match emulator.memory().get(address..address.saturating_add(length)) {
+                    Some(bytes) => hex(bytes),
+                    None => "E01".to_string(),
+                },
+                None => "E01".to_string(),
+            },
+            Some(b'M') => match write_memory_packet(emulator, &packet[1..]) {
+                Some(()) => "OK".to_string(),
+                None => "E01".to_string(),
+            },
+            Some(b'Z') | Some(b'z') => breakpoint_packet(&mut debugger, &packet),
+            Some(b's') => stop_reply(&debugger.step(emulator, 1)),
+            Some(b'c') => match continue_until_stop(&mut connection, &mut debugger, emulator)? {
+                ClientInput::Nothing(stop) => stop_reply(&stop),
+                ClientInput::Interrupt => "S02".to_string(),
+                ClientInput::Disconnected => return Ok(()),
+            },
+            Some(b'H') => "OK".to_string(),
+            Some(b'k') => return Ok(()),
+            Some(b'D') => {
+                connection.write_packet("OK")?;
+                return Ok(());
+            },
+            _ => query_reply(&packet),
+        };
+
+        connection.write_packet(&reply)?;
+    }
+
+    Ok(())
+}
+
+#[doc = "Raw connection, buffered by hand so interrupts can be polled while the program runs"]
+struct Connection {
+    stream: TcpStream,
+    buffer: Vec<u8>,
+}
+
+impl Connection {
+    #[doc = "Blocking read of one byte, None once the client disconnected"]
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        if self.buffer.is_empty() {
+            let mut chunk = [0; 1024];
+            let length = self.stream.read(&mut chunk)?;
+
+            if length == 0 {
+                return Ok(None);
+            }
+            self.buffer.extend_from_slice(&chunk[..length]);
+        }
+
+        Ok(Some(self.buffer.remove(0)))
+    }
+
+    #[doc = "Read the next $data#checksum packet and acknowledge it, stray bytes and acks are skipped"]
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            match self.read_byte()? {
+                None => return Ok(None),
+                Some(b'$') => {},
+                Some(_) => continue,
+            }
+
+            let mut data = Vec::new();
+            loop {
+                match self.read_byte()? {
+                    None => return Ok(None),
+                    Some(b'#') => break,
+                    Some(byte) => data.push(byte),
+                }
+            }
+
+            let mut checksum = [0; 2];
+            for digit in checksum.iter_mut() {
+                match self.read_byte()? {
+                    None => return Ok(None),
+                    Some(byte) => *digit = byte,
+                }
+            }
+
+            let expected = std::str::from_utf8(&checksum).ok().and_then(|checksum| u8::from_str_radix(checksum, 16).ok());
+            if expected != Some(packet_checksum(&data)) {
+                self.stream.write_all(b"-")?;
+                continue;
+            }
+
+            self.stream.write_all(b"+")?;
+            return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+        }
+    }
+
+    fn write_packet(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", data, packet_checksum(data.as_bytes()));
+
+        self.stream.write_all(packet.as_bytes())?;
+        self.stream.flush()
+    }
+
+    #[doc = "Whether the client sent the 0x03 interrupt byte or disconnected, without blocking"]
+    fn poll_interrupt(&mut self) -> io::Result<ClientInput<()>> {
+        self.stream.set_nonblocking(true)?;
+
+        let mut chunk = [0; 1024];
+        let result = match self.stream.read(&mut chunk) {
+            Ok(0) => Ok(false),
+            Ok(length) => {
+                self.buffer.extend_from_slice(&chunk[..length]);
+                Ok(true)
+            },
+            Err(error) if error.kind() == io::ErrorKind::WouldBlock => Ok(true),
+            Err(error) if is_disconnect(&error) => Ok(false),
+            Err(error) => Err(error),
+        };
+
+        self.stream.set_nonblocking(false)?;
+        if !result? {
+            return Ok(ClientInput::Disconnected);
+        }
+
+        match self.buffer.iter().position(|byte| *byte == 0x03) {
+            Some(position) => {
+                self.buffer.remove(position);
+                Ok(ClientInput::Interrupt)
+            },
+            None => Ok(ClientInput::Nothing(())),
+        }
+    }
+}
+
+#[doc = "What the client did while the program ran, Nothing carries the result of the run"]
+enum ClientInput<T> {
+    Nothing(T),
+    Interrupt,
+    Disconnected,
+}
+
+#[doc = "Run until something the client has to know about happens, or until it interrupts or leaves"]
+fn continue_until_stop(connection: &mut Connection, debugger: &mut Debugger, emulator: &mut Emulator) -> io::Result<ClientInput<DebugStop>> {
+    loop {
+        let stop = debugger.resume(emulator, CONTINUE_CHUNK);
+
+        match stop {
+            DebugStop::Limit => {},
+            // The program only moves on with input, which a headless session can't get
+            DebugStop::SelfLoop(_) | DebugStop::WaitingForKey => std::thread::sleep(std::time::Duration::from_millis(1)),
+            _ => return Ok(ClientInput::Nothing(stop)),
+        }
+
+        match connection.poll_interrupt()? {
+            ClientInput::Nothing(()) => {},
+            ClientInput::Interrupt => return Ok(ClientInput::Interrupt),
+            ClientInput::Disconnected => return Ok(ClientInput::Disconnected),
+        }
+    }
+}
+
+#[doc = "S packet with the signal matching the stop, W00 once the program exited"]
+fn stop_reply(stop: &DebugStop) -> String {
+    let signal = match stop {
+        DebugStop::Exit => return "W00".to_string(),
+        DebugStop::Crashed(EmulatorError::UnknownOpcode { .. }) => 4,
+        DebugStop::Crashed(EmulatorError::MemoryOutOfBounds { .. }) => 11,
+        DebugStop::Crashed(_) => 6,
+        _ => 5,
+    };
+
+    format!("S{:02x}", signal)
+}
+
+fn breakpoint_packet(debugger: &mut Debugger, packet: &str) -> String {
+    // Only software breakpoints (type 0) are supported, as Zt,address,kind
+    let mut fields = packet[1..].split(',');
+    let (Some("0"), Some(address)) = (fields.next(), fields.next()) else {
+        return String::new();
+    };
+    let Ok(address) = u16::from_str_radix(address, 16) else {
+        return "E01".to_string();
+    };
+
+    if packet.starts_with('Z') {
+        debugger.add_breakpoint(address);
+    } else {
+        debugger.remove_breakpoint(address);
+    }
+
+    "OK".to_string()
+}
+
+fn query_reply(packet: &str) -> String {
+    if packet.starts_with("qSupported") {
+        return "PacketSize=1000;qXfer:features:read+".to_string();
+    }
+
+    if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+        let Some((offset, length)) = parse_address_length(range) else {
+            return "E01".to_string();
+        };
+        let start = offset.min(TARGET_XML.len());
+        let end = offset.saturating_add(length).min(TARGET_XML.len());
+        let prefix = if end == TARGET_XML.len() { 'l' } else { 'm' };
+
+        return format!("{}{}", prefix, &TARGET_XML[start..end]);
+    }
+
+    match packet {
+        "qAttached" => "1".to_string(),
+        "qfThreadInfo" => "m1".to_string(),
+        "qsThreadInfo" => "l".to_string(),
+        "qC" => "QC1".to_string(),
+        _ => String::new(),
+    }
+}
+
+fn registers(emulator: &Emulator) -> Vec<u8> {
+    (0..=REGISTER_SP).flat_map(|number| register(emulator, number).unwrap_or_default()).collect()
+}
+
+#[doc = "Value of a register as little-endian bytes"]
+fn register(emulator: &Emulator, number: usize) -> Option<Vec<u8>> {
+    match number {
+        0..=15 => Some(vec![emulator.registers()[number]]),
+        REGISTER_I => Some(emulator.i().to_le_bytes().to_vec()),
+        REGISTER_PC => Some(emulator.pc().to_le_bytes().to_vec()),
+        REGISTER_SP => Some(vec![emulator.sp()]),
+        _ => None,
+    }
+}
+
+#[doc = "Set a register from little-endian bytes, SP can only be written with its current value"]
+fn write_register(emulator: &mut Emulator, number: usize, bytes: &[u8]) -> bool {
+    match (number, bytes) {
+        (0..=15, [value]) => emulator.set_register(number as u8, *value),
+        (REGISTER_I, [low, high]) => emulator.set_i(u16::from_le_bytes([*low, *high])),
+        (REGISTER_PC, [low, high]) => emulator.set_pc(u16::from_le_bytes([*low, *high])),
+        (REGISTER_SP, [value]) => return *value == emulator.sp(),
+        _ => return false,
+    }
+
+    true
+}
+
+fn write_registers(emulator: &mut Emulator, bytes: &[u8]) -> bool {
+    if bytes.len() != registers(emulator).len() {
+        return false;
+    }
+
+    let mut offset = 0;
+    for number in 0..=REGISTER_SP {
+        let size = register(emulator, number).map(|value| value.len()).unwrap_or(0);
+        if !write_register(emulator, number, &bytes[offset..offset + size]) {
+            return false;
+        }
+        offset += size;
+    }
+
+    true
+}
+
+#[doc = "Pn...=r... packet body"]
+fn write_register_packet(emulator: &mut Emulator, body: &str) -> Option<()> {
+    let (number, value) = body.split_once('=')?;
+    let number = usize::from_str_radix(number, 16).ok()?;
+
+    write_register(emulator, number, &from_hex(value)?).then_some(())
+}
+
+#[doc = "Maddr,length:XX... packet body"]
+fn write_memory_packet(emulator: &mut Emulator, body: &str) -> Option<()> {
+    let (range, data) = body.split_once(':')?;
+    let (address, length) = parse_address_length(range)?;
+    let bytes = from_hex(data)?;
+
+    if bytes.len() != length || address > u16::MAX as usize {
+        return None;
+    }
+
+    emulator.write_memory(address as u16, &bytes).ok()
+}
+
+fn parse_address_length(text: &str) -> Option<(usize, usize)> {
+    let (address, length) = text.split_once(',')?;
+
+    Some((usize::from_str_radix(address, 16).ok()?, usize::from_str_radix(length, 16).ok()?))
+}
+
+fn packet_checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |checksum, byte| checksum.wrapping_add(*byte))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..text.len()).step_by(2).map(|index| u8::from_str_radix(text.get(index..index + 2)?, 16).ok()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{Quirks, Variant};
+
+    // LD V0, 0x00 / ADD V0, 0x01 / JP 0x202
+    const ROM: [u8; 6] = [0x60, 0x00, 0x70, 0x01, 0x12, 0x02];
+
+    #[doc = "Serve a session on a loopback port from another thread, the receiver gets the result once it ends"]
+    fn start() -> (Client, mpsc::Receiver<io::Result<()>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let (sender, receiver) = mpsc::channel();
+
+        std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut emulator = Emulator::new(ROM.to_vec(), Variant::Chip8, Quirks::default()).unwrap();
+            sender.send(serve(stream, &mut emulator, 10)).unwrap();
+        });
+
+        let stream = TcpStream::connect(address).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        (Client { stream }, receiver)
+    }
+
+    struct Client {
+        stream: TcpStream,
+    }
+
+    impl Client {
+        fn send(&mut self, data: &str) {
+            write!(self.stream, "${}#{:02x}", data, packet_checksum(data.as_bytes())).unwrap();
+
+            let mut ack = [0];
+            self.stream.read_exact(&mut ack).unwrap();
+            assert_eq!(ack[0], b'+');
+        }
+
+        fn reply(&mut self) -> String {
+            let mut packet = Vec::new();
+            let mut byte = [0];
+
+            loop {
+                self.stream.read_exact(&mut byte).unwrap();
+                match byte[0] {
+                    b'$' => packet.clear(),
+                    b'#' => break,
+                    byte => packet.push(byte),
+                }
+            }
+
+            let mut checksum = [0; 2];
+            self.stream.read_exact(&mut checksum).unwrap();
+            assert_eq!(u8::from_str_radix(std::str::from_utf8(&checksum).unwrap(), 16).unwrap(), packet_checksum(&packet));
+
+            String::from_utf8(packet).unwrap()
+        }
+
+        fn request(&mut self, data: &str) -> String {
+            self.send(data);
+            self.reply()
+        }
+    }
+
+    #[test]
+    fn registers_and_memory() {
+        let (mut client, _) = start();
+
+        assert_eq!(client.request("?"), "S05");
+        // V0-VF, I, PC 0x200 and SP
+        assert_eq!(client.request("g"), format!("{}0000000200", "00".repeat(16)));
+        assert_eq!(client.request("m200,6"), "600070011202");
+
+        assert_eq!(client.request("M300,2:abcd"), "OK");
+        assert_eq!(client.request("m300,2"), "abcd");
+        assert_eq!(client.request("M300,2:ab"), "E01");
+
+        assert_eq!(client.request("P3=7f"), "OK");
+        assert_eq!(client.request("p3"), "7f");
+        assert_eq!(client.request("P11=0402"), "OK");
+        assert_eq!(client.request("p11"), "0402");
+        // SP can't be written
+        assert_eq!(client.request("P12=05"), "E01");
+
+        assert_eq!(client.request("D"), "OK");
+    }
+
+    #[test]
+    fn breakpoints_and_stepping() {
+        let (mut client, receiver) = start();
+
+        assert_eq!(client.request("Z0,204,2"), "OK");
+        assert_eq!(client.request("c"), "S05");
+        assert_eq!(client.request("p11"), "0402");
+        assert_eq!(client.request("p0"), "01");
+
+        assert_eq!(client.request("c"), "S05");
+        assert_eq!(client.request("p0"), "02");
+
+        assert_eq!(client.request("z0,204,2"), "OK");
+        assert_eq!(client.request("s"), "S05");
+        assert_eq!(client.request("p11"), "0202");
+
+        // Unsupported breakpoint types get an empty reply
+        assert_eq!(client.request("Z2,300,1"), "");
+
+        client.send("k");
+        assert!(receiver.recv_timeout(Duration::from_secs(5)).unwrap().is_ok());
+    }
+
+    #[test]
+    fn disconnect_while_running() {
+        let (mut client, receiver) = start();
+
+        // Without breakpoints the program runs until the client interrupts or leaves
+        client.send("c");
+        drop(client);
+
+        assert!(receiver.recv_timeout(Duration::from_secs(5)).unwrap().is_ok());
+    }
+
+    #[test]
+    fn interrupt_while_running() {
+        let (mut client, _) = start();
+
+        client.send("c");
+        client.stream.write_all(&[0x03]).unwrap();
+        assert_eq!(client.reply(), "S02");
+        assert_eq!(client.request("?"), "S05");
+    }
+}
@@ -0,0 +1,132 @@
+use std::fmt;
+
+use crate::emulator::Emulator;
+use crate::error::{EmulatorError, StepOutcome};
+use crate::instruction::Instruction;
+use crate::keypad::Keypad;
+use crate::movie::Movie;
+
+#[doc = "Why a headless run stopped"]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StopReason {
+    #[doc = "Every requested frame ran"]
+    FramesElapsed,
+    #[doc = "The program jumps to itself at the given address, which is how most test roms end"]
+    SelfLoop(u16),
+    Exit,
+    Crashed(EmulatorError),
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StopReason::FramesElapsed => write!(f, "ran every frame"),
+            StopReason::SelfLoop(address) => write!(f, "program loops on itself at 0x{:03X}", address),
+            StopReason::Exit => write!(f, "program exited"),
+            StopReason::Crashed(error) => write!(f, "crashed: {}", error),
+        }
+    }
+}
+
+#[doc = "Run up to the given number of frames without any input, returning how many frames ran and why it stopped"]
+pub fn run(emulator: &mut Emulator, instructions_per_frame: u32, frames: u32) -> (u32, StopReason) {
+    for frame in 0..frames {
+        match emulator.run_frame(instructions_per_frame) {
+            Ok(StepOutcome::Exit) => return (frame + 1, StopReason::Exit),
+            Ok(_) => {},
+            Err(error) => return (frame + 1, StopReason::Crashed(error)),
+        }
+
+        if let Ok(Instruction::Jp { addr }) = emulator.current_instruction() {
+            if addr == emulator.pc() {
+                return (frame + 1, StopReason::SelfLoop(addr));
+            }
+        }
+    }
+
+    (frames, StopReason::FramesElapsed)
+}
+
+#[doc = "Run every frame of a movie with its recorded keypad, returning how many frames ran and why it stopped"]
+#[doc = ""]
+#[doc = "Unlike run(), a program looping on itself doesn't stop the replay, the movie may still press keys"]
+pub fn replay(emulator: &mut Emulator, movie: &Movie) -> (u32, StopReason) {
+    for (frame, mask) in movie.frames.iter().enumerate() {
+        emulator.set_keypad(Keypad::from_mask(*mask));
+
+        match emulator.run_frame(movie.instructions_per_frame) {
+            Ok(StepOutcome::Exit) => return (frame as u32 + 1, StopReason::Exit),
+            Ok(_) => {},
+            Err(error) => return (frame as u32 + 1, StopReason::Crashed(error)),
+        }
+    }
+
+    (movie.frames.len() as u32, StopReason::FramesElapsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Quirks, Variant};
+
+    fn emulator(rom: &[u8]) -> Emulator {
+        Emulator::new(rom.to_vec(), Variant::Chip8, Quirks::default()).unwrap()
+    }
+
+    #[test]
+    fn run_stops_after_the_frames() {
+        // ADD V0, 1 / JP 0x200
+        let mut emulator = emulator(&[0x70, 0x01, 0x12, 0x00]);
+
+        assert_eq!(run(&mut emulator, 10, 5), (5, StopReason::FramesElapsed));
+        assert_eq!(emulator.registers()[0], 25);
+    }
+
+    #[test]
+    fn run_stops_on_a_self_loop() {
+        // LD V0, 1 / JP 0x202
+        let mut emulator = emulator(&[0x60, 0x01, 0x12, 0x02]);
+
+        assert_eq!(run(&mut emulator, 10, 5), (1, StopReason::SelfLoop(0x202)));
+        assert_eq!(emulator.registers()[0], 1);
+    }
+
+    #[test]
+    fn run_stops_on_errors() {
+        // ADD V0, 1 / SE V0, 5 / JP 0x200, then an unknown opcode once V0 reaches 5
+        let mut emulator = emulator(&[0x70, 0x01, 0x30, 0x05, 0x12, 0x00, 0x00, 0x00]);
+
+        let (frames, stop_reason) = run(&mut emulator, 3, 10);
+        assert_eq!(frames, 5);
+        assert_eq!(stop_reason, StopReason::Crashed(EmulatorError::UnknownOpcode { pc: 0x206, opcode: 0x0000 }));
+    }
+
+    #[test]
+    fn replay_presses_the_recorded_keys() {
+        // LD V0, K / LD F, V0 / DRW V1, V1, 5 / JP 0x206
+        let rom = [0xF0, 0x0A, 0xF0, 0x29, 0xD1, 0x15, 0x12, 0x06];
+
+        let mut movie = Movie::new(Variant::Chip8, Quirks::default(), 11, 0, 0);
+        movie.frames = vec![0, 0, 1 << 5, 1 << 5, 0, 0, 0, 0];
+
+        let mut replayed = emulator(&rom);
+        // Looping on itself from the fifth frame on doesn't end the replay
+        assert_eq!(replay(&mut replayed, &movie), (8, StopReason::FramesElapsed));
+        assert_eq!(replayed.registers()[0], 5);
+
+        // Same screen as drawing the digit directly
+        let mut expected = emulator(&[0x60, 0x05, 0xF0, 0x29, 0xD1, 0x15, 0x12, 0x06]);
+        expected.run_frame(11).unwrap();
+        assert_eq!(replayed.screen_hash(), expected.screen_hash());
+    }
+
+    #[test]
+    fn replay_stops_on_errors() {
+        let mut movie = Movie::new(Variant::Chip8, Quirks::default(), 11, 0, 0);
+        movie.frames = vec![0; 4];
+
+        let (frames, stop_reason) = replay(&mut emulator(&[0x00, 0x00]), &movie);
+        assert_eq!(frames, 1);
+        assert_eq!(stop_reason, StopReason::Crashed(EmulatorError::UnknownOpcode { pc: 0x200, opcode: 0x0000 }));
+    }
+}
@@ -0,0 +1,56 @@
+use nauka::Keypad;
+use sdl2::event::Event;
+
+use crate::controllers::Controllers;
+use crate::keymap::{Input, Keymap};
+
+#[doc = "Keyboard keys and controller buttons held, tracked from their down and up events"]
+pub struct InputState {
+    keymap: Keymap,
+    controllers: Controllers,
+    held: Vec<Input>,
+    live: bool,
+}
+
+impl InputState {
+    pub fn new(keymap: Keymap, controllers: Controllers) -> Self {
+        Self {
+            keymap,
+            controllers,
+            held: Vec::new(),
+            live: true,
+        }
+    }
+
+    #[doc = "Whether key changes reach the emulator as they happen, movies only take the keypad between frames"]
+    pub fn is_live(&self) -> bool {
+        self.live
+    }
+
+    pub fn set_live(&mut self, live: bool) {
+        self.live = live;
+    }
+
+    #[doc = "Returns whether the event pressed or released a key of the keypad"]
+    pub fn handle_event(&mut self, event: &Event) -> bool {
+        let changes = match *event {
+            Event::KeyDown { scancode: Some(scancode), repeat: false, .. } => vec![(Input::Key(scancode), true)],
+            Event::KeyUp { scancode: Some(scancode), .. } => vec![(Input::Key(scancode), false)],
+            _ => self.controllers.handle_event(event).into_iter().map(|(button, pressed)| (Input::Button(button), pressed)).collect(),
+        };
+
+        let keypad = self.keypad();
+        for (input, pressed) in changes {
+            self.held.retain(|held| *held != input);
+            if pressed {
+                self.held.push(input);
+            }
+        }
+
+        self.keypad() != keypad
+    }
+
+    pub fn keypad(&self) -> Keypad {
+        self.keymap.keypad(&self.held)
+    }
+}
@@ -0,0 +1,347 @@
+use std::fmt;
+
+use crate::opcode::{Opcode, ZeroOpcode, FiveOpcode, EightOpcode, FifteenOpcode, FourteenOpcode};
+
+#[doc = "Decoded instruction with its operands, x and y are register indices"]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    Cls,
+    Ret,
+    Jp { addr: u16 },
+    Call { addr: u16 },
+    SeVxByte { x: u8, byte: u8 },
+    SneVxByte { x: u8, byte: u8 },
+    SeVxVy { x: u8, y: u8 },
+    LdVxByte { x: u8, byte: u8 },
+    AddVxByte { x: u8, byte: u8 },
+    LdVxVy { x: u8, y: u8 },
+    OrVxVy { x: u8, y: u8 },
+    AndVxVy { x: u8, y: u8 },
+    XorVxVy { x: u8, y: u8 },
+    AddVxVy { x: u8, y: u8 },
+    SubVxVy { x: u8, y: u8 },
+    ShrVxVy { x: u8, y: u8 },
+    SubnVxVy { x: u8, y: u8 },
+    ShlVxVy { x: u8, y: u8 },
+    SneVxVy { x: u8, y: u8 },
+    LdIAddr { addr: u16 },
+    #[doc = "x is only used with the jump quirk, where BXNN jumps to XNN + VX"]
+    JpV0Addr { x: u8, addr: u16 },
+    RndVxByte { x: u8, byte: u8 },
+    DrwVxVyN { x: u8, y: u8, n: u8 },
+    SkpVx { x: u8 },
+    SknpVx { x: u8 },
+    LdVxDt { x: u8 },
+    LdVxK { x: u8 },
+    LdDtVx { x: u8 },
+    LdStVx { x: u8 },
+    AddIVx { x: u8 },
+    LdFVx { x: u8 },
+    LdBVx { x: u8 },
+    LdIVx { x: u8 },
+    LdVxI { x: u8 },
+    // SUPER-CHIP
+    ScdN { n: u8 },
+    Scr,
+    Scl,
+    Exit,
+    Low,
+    High,
+    LdHfVx { x: u8 },
+    LdRVx { x: u8 },
+    LdVxR { x: u8 },
+    // XO-CHIP
+    ScuN { n: u8 },
+    SaveVxVy { x: u8, y: u8 },
+    LoadVxVy { x: u8, y: u8 },
+    #[doc = "The address is stored in the 16 bits following the opcode"]
+    LdILong { addr: u16 },
+    Plane { planes: u8 },
+    Audio,
+    PitchVx { x: u8 },
+}
+
+#[doc = "The opcode doesn't match any known instruction"]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeError {
+    pub opcode: u16,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown opcode 0x{:04X}", self.opcode)
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+impl Instruction {
+    #[doc = "Size of the instruction in bytes, operands following the opcode included"]
+    pub fn length(&self) -> u16 {
+        match self {
+            Instruction::LdILong { .. } => 4,
+            _ => 2,
+        }
+    }
+
+    #[doc = "Whether the instruction only exists in SUPER-CHIP and later"]
+    pub fn is_super_chip(&self) -> bool {
+        matches!(self, Instruction::ScdN { .. } | Instruction::Scr | Instruction::Scl | Instruction::Exit | Instruction::Low | Instruction::High
+            | Instruction::LdHfVx { .. } | Instruction::LdRVx { .. } | Instruction::LdVxR { .. })
+    }
+
+    #[doc = "Whether the instruction only exists in XO-CHIP"]
+    pub fn is_xo_chip(&self) -> bool {
+        matches!(self, Instruction::ScuN { .. } | Instruction::SaveVxVy { .. } | Instruction::LoadVxVy { .. } | Instruction::LdILong { .. }
+            | Instruction::Plane { .. } | Instruction::Audio | Instruction::PitchVx { .. })
+    }
+}
+
+#[doc = "Decode a 16 bit opcode, instructions of every variant are recognized"]
+#[doc = ""]
+#[doc = "`LD I, LONG` is rejected as its address follows the opcode, decode_at() reads it"]
+pub fn decode(opcode: u16) -> Result<Instruction, DecodeError> {
+    decode_with_operand(opcode, None)
+}
+
+#[doc = "Decode the instruction at the start of the given bytes, the bytes past it are ignored"]
+pub fn decode_at(bytes: &[u8]) -> Result<Instruction, DecodeError> {
+    let word = |offset: usize| bytes.get(offset..offset + 2).map(|word| ((word[0] as u16) << 8) | (word[1] as u16));
+
+    let opcode = word(0).ok_or(DecodeError { opcode: bytes.first().map_or(0, |byte| (*byte as u16) << 8) })?;
+
+    decode_with_operand(opcode, word(2))
+}
+
+#[doc = "The long operand is the 16 bits following the opcode, if there are any"]
+fn decode_with_operand(opcode: u16, long_operand: Option<u16>) -> Result<Instruction, DecodeError> {
+    let error = DecodeError { opcode };
+
+    let x = ((opcode & 0x0F00) >> 8) as u8;
+    let y = ((opcode & 0x00F0) >> 4) as u8;
+    let n = (opcode & 0x000F) as u8;
+    let byte = (opcode & 0x00FF) as u8;
+    let addr = opcode & 0x0FFF;
+
+    let instruction = match num::FromPrimitive::from_u16(opcode & 0xF000).ok_or(error)? {
+        Opcode::ZeroOpcode => {
+            if x != 0 {
+                return Err(error);
+            }
+
+            // SCD and SCU carry the scroll amount in their lowest nibble
+            let zero_opcode = match opcode & 0x00F0 {
+                0x00C0 | 0x00D0 => opcode & 0x00F0,
+                _ => opcode & 0x00FF,
+            };
+
+            match num::FromPrimitive::from_u16(zero_opcode).ok_or(error)? {
+                ZeroOpcode::CLS => Instruction::Cls,
+                ZeroOpcode::RET => Instruction::Ret,
+                ZeroOpcode::SCD => Instruction::ScdN { n },
+                ZeroOpcode::SCU => Instruction::ScuN { n },
+                ZeroOpcode::SCR => Instruction::Scr,
+                ZeroOpcode::SCL => Instruction::Scl,
+                ZeroOpcode::EXIT => Instruction::Exit,
+                ZeroOpcode::LOW => Instruction::Low,
+                ZeroOpcode::HIGH => Instruction::High,
+            }
+        },
+        Opcode::JpAddr => Instruction::Jp { addr },
+        Opcode::CallAddr => Instruction::Call { addr },
+        Opcode::SeVxByte => Instruction::SeVxByte { x, byte },
+        Opcode::SneVxByte => Instruction::SneVxByte { x, byte },
+        Opcode::SeVxVy => match num::FromPrimitive::from_u16(opcode & 0x000F).ok_or(error)? {
+            FiveOpcode::SeVxVy => Instruction::SeVxVy { x, y },
+            FiveOpcode::SaveVxVy => Instruction::SaveVxVy { x, y },
+            FiveOpcode::LoadVxVy => Instruction::LoadVxVy { x, y },
+        },
+        Opcode::LdVxByte => Instruction::LdVxByte { x, byte },
+        Opcode::AddVxByte => Instruction::AddVxByte { x, byte },
+        Opcode::EightOpcode => match num::FromPrimitive::from_u16(opcode & 0x000F).ok_or(error)? {
+            EightOpcode::LdVxVy => Instruction::LdVxVy { x, y },
+            EightOpcode::OrVxVy => Instruction::OrVxVy { x, y },
+            EightOpcode::AndVxVy => Instruction::AndVxVy { x, y },
+            EightOpcode::XorVxVy => Instruction::XorVxVy { x, y },
+            EightOpcode::AddVxVy => Instruction::AddVxVy { x, y },
+            EightOpcode::SubVxVy => Instruction::SubVxVy { x, y },
+            EightOpcode::ShrVx => Instruction::ShrVxVy { x, y },
+            EightOpcode::SubnVxVy => Instruction::SubnVxVy { x, y },
+            EightOpcode::ShlVx => Instruction::ShlVxVy { x, y },
+        },
+        Opcode::SneVxVy => {
+            if n != 0 {
+                return Err(error);
+            }
+
+            Instruction::SneVxVy { x, y }
+        },
+        Opcode::LdIAddr => Instruction::LdIAddr { addr },
+        Opcode::JpV0Addr => Instruction::JpV0Addr { x, addr },
+        Opcode::RndVxByte => Instruction::RndVxByte { x, byte },
+        Opcode::DrwVxVy => Instruction::DrwVxVyN { x, y, n },
+        Opcode::FourteenOpcode => match num::FromPrimitive::from_u16(opcode & 0x00FF).ok_or(error)? {
+            FourteenOpcode::SkpVx => Instruction::SkpVx { x },
+            FourteenOpcode::SkpnVx => Instruction::SknpVx { x },
+        },
+        Opcode::FifteenOpcode => match num::FromPrimitive::from_u16(opcode & 0x00FF).ok_or(error)? {
+            FifteenOpcode::LdILong if x == 0 => Instruction::LdILong { addr: long_operand.ok_or(error)? },
+            FifteenOpcode::Plane if x <= 0b11 => Instruction::Plane { planes: x },
+            FifteenOpcode::Audio if x == 0 => Instruction::Audio,
+            FifteenOpcode::LdVxDt => Instruction::LdVxDt { x },
+            FifteenOpcode::LdVxK => Instruction::LdVxK { x },
+            FifteenOpcode::LdDtVx => Instruction::LdDtVx { x },
+            FifteenOpcode::LdStVx => Instruction::LdStVx { x },
+            FifteenOpcode::AddIVx => Instruction::AddIVx { x },
+            FifteenOpcode::LdFVx => Instruction::LdFVx { x },
+            FifteenOpcode::LdHfVx => Instruction::LdHfVx { x },
+            FifteenOpcode::LdBVx => Instruction::LdBVx { x },
+            FifteenOpcode::PitchVx => Instruction::PitchVx { x },
+            FifteenOpcode::LdIVx => Instruction::LdIVx { x },
+            FifteenOpcode::LdVxI => Instruction::LdVxI { x },
+            FifteenOpcode::LdRVx => Instruction::LdRVx { x },
+            FifteenOpcode::LdVxR => Instruction::LdVxR { x },
+            _ => return Err(error),
+        },
+    };
+
+    Ok(instruction)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decoded(opcode: u16) -> Instruction {
+        decode(opcode).unwrap_or_else(|error| panic!("{}", error))
+    }
+
+    fn rejected(opcode: u16) {
+        assert_eq!(decode(opcode), Err(DecodeError { opcode }), "0x{:04X} should be rejected", opcode);
+    }
+
+    #[test]
+    fn zero_group() {
+        assert_eq!(decoded(0x00E0), Instruction::Cls);
+        assert_eq!(decoded(0x00EE), Instruction::Ret);
+        assert_eq!(decoded(0x00C5), Instruction::ScdN { n: 5 });
+        assert_eq!(decoded(0x00DA), Instruction::ScuN { n: 0xA });
+        assert_eq!(decoded(0x00FB), Instruction::Scr);
+        assert_eq!(decoded(0x00FC), Instruction::Scl);
+        assert_eq!(decoded(0x00FD), Instruction::Exit);
+        assert_eq!(decoded(0x00FE), Instruction::Low);
+        assert_eq!(decoded(0x00FF), Instruction::High);
+
+        // 0NNN machine code routines aren't supported
+        rejected(0x01E0);
+        rejected(0x0123);
+        rejected(0x00E1);
+    }
+
+    #[test]
+    fn address_and_byte_groups() {
+        assert_eq!(decoded(0x1234), Instruction::Jp { addr: 0x234 });
+        assert_eq!(decoded(0x2ABC), Instruction::Call { addr: 0xABC });
+        assert_eq!(decoded(0x3A12), Instruction::SeVxByte { x: 0xA, byte: 0x12 });
+        assert_eq!(decoded(0x4B34), Instruction::SneVxByte { x: 0xB, byte: 0x34 });
+        assert_eq!(decoded(0x6C56), Instruction::LdVxByte { x: 0xC, byte: 0x56 });
+        assert_eq!(decoded(0x7D78), Instruction::AddVxByte { x: 0xD, byte: 0x78 });
+        assert_eq!(decoded(0xA9AB), Instruction::LdIAddr { addr: 0x9AB });
+        assert_eq!(decoded(0xB3CD), Instruction::JpV0Addr { x: 3, addr: 0x3CD });
+        assert_eq!(decoded(0xC7EF), Instruction::RndVxByte { x: 7, byte: 0xEF });
+        assert_eq!(decoded(0xD12F), Instruction::DrwVxVyN { x: 1, y: 2, n: 0xF });
+    }
+
+    #[test]
+    fn register_pair_groups() {
+        assert_eq!(decoded(0x5120), Instruction::SeVxVy { x: 1, y: 2 });
+        assert_eq!(decoded(0x5342), Instruction::SaveVxVy { x: 3, y: 4 });
+        assert_eq!(decoded(0x5563), Instruction::LoadVxVy { x: 5, y: 6 });
+        assert_eq!(decoded(0x9AB0), Instruction::SneVxVy { x: 0xA, y: 0xB });
+
+        rejected(0x5121);
+        rejected(0x5124);
+        rejected(0x9AB1);
+    }
+
+    #[test]
+    fn eight_group() {
+        assert_eq!(decoded(0x8120), Instruction::LdVxVy { x: 1, y: 2 });
+        assert_eq!(decoded(0x8121), Instruction::OrVxVy { x: 1, y: 2 });
+        assert_eq!(decoded(0x8122), Instruction::AndVxVy { x: 1, y: 2 });
+        assert_eq!(decoded(0x8123), Instruction::XorVxVy { x: 1, y: 2 });
+        assert_eq!(decoded(0x8124), Instruction::AddVxVy { x: 1, y: 2 });
+        assert_eq!(decoded(0x8125), Instruction::SubVxVy { x: 1, y: 2 });
+        assert_eq!(decoded(0x8126), Instruction::ShrVxVy { x: 1, y: 2 });
+        assert_eq!(decoded(0x8127), Instruction::SubnVxVy { x: 1, y: 2 });
+        assert_eq!(decoded(0x812E), Instruction::ShlVxVy { x: 1, y: 2 });
+
+        for n in [0x8, 0x9, 0xA, 0xB, 0xC, 0xD, 0xF] {
+            rejected(0x8120 | n);
+        }
+    }
+
+    #[test]
+    fn key_group() {
+        assert_eq!(decoded(0xE59E), Instruction::SkpVx { x: 5 });
+        assert_eq!(decoded(0xE6A1), Instruction::SknpVx { x: 6 });
+
+        rejected(0xE59F);
+        rejected(0xE6A0);
+    }
+
+    #[test]
+    fn fifteen_group() {
+        assert_eq!(decoded(0xF107), Instruction::LdVxDt { x: 1 });
+        assert_eq!(decoded(0xF20A), Instruction::LdVxK { x: 2 });
+        assert_eq!(decoded(0xF315), Instruction::LdDtVx { x: 3 });
+        assert_eq!(decoded(0xF418), Instruction::LdStVx { x: 4 });
+        assert_eq!(decoded(0xF51E), Instruction::AddIVx { x: 5 });
+        assert_eq!(decoded(0xF629), Instruction::LdFVx { x: 6 });
+        assert_eq!(decoded(0xF730), Instruction::LdHfVx { x: 7 });
+        assert_eq!(decoded(0xF833), Instruction::LdBVx { x: 8 });
+        assert_eq!(decoded(0xF93A), Instruction::PitchVx { x: 9 });
+        assert_eq!(decoded(0xFA55), Instruction::LdIVx { x: 0xA });
+        assert_eq!(decoded(0xFB65), Instruction::LdVxI { x: 0xB });
+        assert_eq!(decoded(0xFC75), Instruction::LdRVx { x: 0xC });
+        assert_eq!(decoded(0xFD85), Instruction::LdVxR { x: 0xD });
+        assert_eq!(decoded(0xF002), Instruction::Audio);
+        assert_eq!(decoded(0xF001), Instruction::Plane { planes: 0 });
+        assert_eq!(decoded(0xF301), Instruction::Plane { planes: 3 });
+
+        // PLANE only has 2 bitplanes to select, AUDIO and LD I, LONG have no register
+        rejected(0xF401);
+        rejected(0xF102);
+        rejected(0xF100);
+        rejected(0xF0FF);
+    }
+
+    #[test]
+    fn long_load_reads_the_following_word() {
+        assert_eq!(decode_at(&[0xF0, 0x00, 0x12, 0x34]), Ok(Instruction::LdILong { addr: 0x1234 }));
+        assert_eq!(decode_at(&[0xF0, 0x00, 0xAB, 0xCD, 0x00, 0xE0]), Ok(Instruction::LdILong { addr: 0xABCD }));
+        assert_eq!(Instruction::LdILong { addr: 0 }.length(), 4);
+
+        // The operand is missing
+        assert_eq!(decode(0xF000), Err(DecodeError { opcode: 0xF000 }));
+        assert_eq!(decode_at(&[0xF0, 0x00, 0x12]), Err(DecodeError { opcode: 0xF000 }));
+    }
+
+    #[test]
+    fn decode_at_ignores_the_following_bytes() {
+        assert_eq!(decode_at(&[0x00, 0xE0, 0xF0, 0x00]), Ok(Instruction::Cls));
+        assert_eq!(decode_at(&[0x81, 0x28]), Err(DecodeError { opcode: 0x8128 }));
+        assert_eq!(decode_at(&[0x12]), Err(DecodeError { opcode: 0x1200 }));
+        assert_eq!(decode_at(&[]), Err(DecodeError { opcode: 0 }));
+    }
+
+    #[test]
+    fn variant_groups() {
+        assert!(decoded(0x00FF).is_super_chip());
+        assert!(!decoded(0x00FF).is_xo_chip());
+        assert!(decoded(0x5342).is_xo_chip());
+        assert!(decode_at(&[0xF0, 0x00, 0x12, 0x34]).unwrap().is_xo_chip());
+        assert!(!decoded(0x8124).is_super_chip());
+        assert!(!decoded(0x8124).is_xo_chip());
+    }
+}
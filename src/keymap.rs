@@ -0,0 +1,130 @@
+use std::path::Path;
+
+use nauka::Keypad;
+use sdl2::controller::Button;
+use sdl2::keyboard::Scancode;
+
+#[doc = "Keyboard keys of the COSMAC VIP layout, in keypad order from 0x0 to 0xF"]
+const DEFAULT_LAYOUT: [Scancode; 16] = [
+    Scancode::X, Scancode::Num1, Scancode::Num2, Scancode::Num3,
+    Scancode::Q, Scancode::W, Scancode::E, Scancode::A,
+    Scancode::S, Scancode::D, Scancode::Z, Scancode::C,
+    Scancode::Num4, Scancode::R, Scancode::F, Scancode::V
+];
+
+#[doc = "Controller buttons bound by default, the D-pad is 2/4/6/8 and A is 5"]
+const DEFAULT_BUTTONS: [(u8, Button); 5] = [
+    (0x2, Button::DPadUp),
+    (0x4, Button::DPadLeft),
+    (0x6, Button::DPadRight),
+    (0x8, Button::DPadDown),
+    (0x5, Button::A)
+];
+
+#[doc = "Keyboard key or controller button"]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Input {
+    Key(Scancode),
+    Button(Button),
+}
+
+#[doc = "Inputs bound to each keypad key, 1234/QWER/ASDF/ZXCV and the D-pad by default"]
+#[doc = ""]
+#[doc = "Keymap files override some keys with lines like `5 = W, Up, button:a`, using SDL scancode and controller button names"]
+#[doc = "A line lists every input of its keypad key, the default bindings of that key are dropped"]
+#[derive(Debug, Clone)]
+pub struct Keymap {
+    bindings: [Vec<Input>; 16],
+}
+
+impl Keymap {
+    pub fn new() -> Self {
+        let mut bindings = DEFAULT_LAYOUT.map(|scancode| vec![Input::Key(scancode)]);
+
+        for (key, button) in DEFAULT_BUTTONS {
+            bindings[key as usize].push(Input::Button(button));
+        }
+
+        Self { bindings }
+    }
+
+    #[doc = "The default layout, overridden by the given keymap file and then by `game.keymap` next to the rom"]
+    pub fn load(path: Option<&Path>, rom_path: &Path) -> Result<Self, String> {
+        let mut keymap = Self::new();
+
+        if let Some(path) = path {
+            let text = std::fs::read_to_string(path).map_err(|error| format!("{}: {}", path.display(), error))?;
+            keymap.apply(&text).map_err(|error| format!("{}: {}", path.display(), error))?;
+        }
+
+        let rom_keymap = rom_path.with_extension("keymap");
+        if let Ok(text) = std::fs::read_to_string(&rom_keymap) {
+            keymap.apply(&text).map_err(|error| format!("{}: {}", rom_keymap.display(), error))?;
+        }
+
+        Ok(keymap)
+    }
+
+    #[doc = "Replace the bindings of the keypad keys listed in a keymap file, # starts a comment"]
+    pub fn apply(&mut self, text: &str) -> Result<(), String> {
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let error = |message: String| format!("line {}: {}", number + 1, message);
+            let (key, inputs) = line.split_once('=').ok_or_else(|| error("expected <key> = <inputs>".to_string()))?;
+
+            let key = key.trim();
+            let key = u8::from_str_radix(key, 16).ok().filter(|key| *key < 16).ok_or_else(|| error(format!("'{}' isn't a keypad key, keys go from 0 to F", key)))?;
+
+            let inputs = inputs
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(|name| parse_input(name).ok_or_else(|| error(format!("unknown input '{}'", name))))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            // An input drives a single keypad key
+            for bindings in self.bindings.iter_mut() {
+                bindings.retain(|input| !inputs.contains(input));
+            }
+            self.bindings[key as usize] = inputs;
+        }
+
+        Ok(())
+    }
+
+    #[doc = "Keypad key bound to an input"]
+    pub fn key(&self, input: Input) -> Option<u8> {
+        self.bindings.iter().position(|bindings| bindings.contains(&input)).map(|key| key as u8)
+    }
+
+    #[doc = "Keypad keys bound to the inputs held"]
+    pub fn keypad(&self, held: &[Input]) -> Keypad {
+        let mut keypad = Keypad::new();
+
+        for input in held {
+            if let Some(key) = self.key(*input) {
+                keypad.press(key);
+            }
+        }
+
+        keypad
+    }
+}
+
+#[doc = "SDL scancode name, or `button:` followed by an SDL controller button name such as a, start or dpup"]
+fn parse_input(name: &str) -> Option<Input> {
+    match name.strip_prefix("button:") {
+        Some(button) => Button::from_string(button).map(Input::Button),
+        None => Scancode::from_name(name).map(Input::Key),
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,78 @@
+#[doc = "Change of a key between two keypad states"]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyEvent {
+    Pressed(u8),
+    Released(u8),
+}
+
+#[doc = "State of the 16-key hexadecimal keypad, one bit per key (bit 0 is key 0x0, bit 15 is key 0xF)"]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Keypad {
+    pressed: u16,
+}
+
+impl Keypad {
+    pub fn new() -> Self {
+        Self { pressed: 0 }
+    }
+
+    pub fn from_mask(mask: u16) -> Self {
+        Self { pressed: mask }
+    }
+
+    pub fn mask(&self) -> u16 {
+        self.pressed
+    }
+
+    #[doc = "Mark a key (0x0 - 0xF) as pressed, keys outside of the keypad are ignored"]
+    pub fn press(&mut self, key: u8) {
+        if key < 16 {
+            self.pressed |= 1 << key;
+        }
+    }
+
+    #[doc = "Mark a key (0x0 - 0xF) as released, keys outside of the keypad are ignored"]
+    pub fn release(&mut self, key: u8) {
+        if key < 16 {
+            self.pressed &= !(1 << key);
+        }
+    }
+
+    pub fn is_pressed(&self, key: u8) -> bool {
+        key < 16 && self.pressed & (1 << key) != 0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pressed == 0
+    }
+
+    pub fn clear(&mut self) {
+        self.pressed = 0;
+    }
+
+    #[doc = "Keys pressed and released going from this state to the next one, in key order"]
+    pub fn events_to(&self, next: &Keypad) -> impl Iterator<Item = KeyEvent> {
+        let (before, after) = (self.pressed, next.pressed);
+
+        (0..16u8).filter_map(move |key| match ((before >> key) & 1, (after >> key) & 1) {
+            (0, 1) => Some(KeyEvent::Pressed(key)),
+            (1, 0) => Some(KeyEvent::Released(key)),
+            _ => None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_between_states() {
+        let before = Keypad::from_mask(0b1010_0000_0000_0011);
+        let after = Keypad::from_mask(0b0010_0000_0001_0001);
+
+        let events: Vec<KeyEvent> = before.events_to(&after).collect();
+        assert_eq!(events, [KeyEvent::Released(1), KeyEvent::Pressed(4), KeyEvent::Released(0xF)]);
+        assert_eq!(after.events_to(&after).count(), 0);
+    }
+}
@@ -0,0 +1,29 @@
+pub mod assembler;
+pub mod audio;
+pub mod checksum;
+pub mod debugger;
+pub mod disassembler;
+pub mod emulator;
+pub mod gdb_stub;
+pub mod error;
+pub mod headless;
+pub mod instruction;
+pub mod keypad;
+pub mod movie;
+pub mod opcode;
+pub mod quirks;
+pub mod rewind;
+pub mod rng;
+pub mod save_state;
+pub mod screen;
+pub mod trace;
+pub mod variant;
+
+pub use emulator::Emulator;
+pub use error::{EmulatorError, StepOutcome};
+pub use instruction::{decode, decode_at, DecodeError, Instruction};
+pub use keypad::Keypad;
+pub use quirks::{Quirks, QuirksPreset};
+pub use rng::RandomSource;
+pub use save_state::SaveStateError;
+pub use variant::Variant;
@@ -1,15 +1,66 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::net::TcpListener;
 use std::path::Path;
 
 use clap::Parser;
-use emulator::Emulator;
-use frame_calculator::FrameCalculator;
-use sdl2::{render::Canvas, video::Window, EventPump};
+use nauka::{assembler, audio::Waveform, disassembler, emulator::PROGRAM_ADDRESS, gdb_stub, headless::{self, StopReason}, movie::Movie, screen::{self, Palette, ScaleFilter, Theme}, trace::{AddressRange, Tracer}, Emulator, Quirks, QuirksPreset, Variant};
 
-mod opcode;
-mod emulator;
+#[cfg(feature = "sdl")]
+mod audio_device;
+#[cfg(feature = "sdl")]
+mod controllers;
+mod debug_console;
+#[cfg(feature = "sdl")]
 mod frame_calculator;
+#[cfg(feature = "sdl")]
+mod frame_scheduler;
+#[cfg(feature = "sdl")]
+mod frontend;
+#[cfg(feature = "sdl")]
+mod input;
+#[cfg(feature = "sdl")]
+mod keymap;
+#[cfg(feature = "sdl")]
+mod renderer;
+#[cfg(feature = "sdl")]
+mod save_slots;
 
 #[derive(Debug, clap::Parser)]
+#[command(name = "nauka")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command
+}
+
+#[derive(Debug, clap::Subcommand)]
+pub enum Command {
+    #[doc = "Run a rom in the emulator"]
+    Run(AppConfiguration),
+    #[doc = "Print a rom as a list of instructions"]
+    Disasm(DisasmConfiguration),
+    #[doc = "Assemble a source file into a rom"]
+    Asm(AsmConfiguration)
+}
+
+#[derive(Debug, clap::Args)]
+pub struct AsmConfiguration {
+    #[doc = "Specify the source file path"]
+    pub input: String,
+
+    #[doc = "Specify the output rom path"]
+    #[arg(short, long)]
+    pub output: String
+}
+
+#[derive(Debug, clap::Args)]
+pub struct DisasmConfiguration {
+    #[doc = "Specify the Chip8 rom path"]
+    #[arg(long)]
+    pub rom: String
+}
+
+#[derive(Debug, clap::Args)]
 pub struct AppConfiguration {
     #[doc = "Specify the Chip8 rom path"]
     #[arg(long)]
@@ -29,98 +80,300 @@ pub struct AppConfiguration {
 
     #[doc = "Specify the height of the window"]
     #[arg(long, default_value_t = 600)]
-    pub height: u32
-}
+    pub height: u32,
 
-#[derive(Debug, PartialEq)]
-pub enum AppStatus {
-    Continue,
-    Exit
-}
+    #[doc = "Specify the filtering of the scaled screen, either nearest or linear"]
+    #[arg(long, default_value = "nearest")]
+    pub filter: ScaleFilter,
+
+    #[doc = "Only scale the screen by whole factors, the window is letterboxed to keep the aspect ratio either way"]
+    #[arg(long, default_value_t = false)]
+    pub integer_scaling: bool,
+
+    #[doc = "Specify the color theme: mono, amber, green, lcd or octo, F2 switches themes while running"]
+    #[arg(long, default_value = "mono")]
+    pub theme: Theme,
+
+    #[doc = "Specify the 4 colors of the background, plane 1, plane 2 and both planes, e.g. 000000,FFFFFF,AAAAAA,555555"]
+    #[arg(long, value_parser = screen::parse_palette)]
+    pub palette: Option<Palette>,
+
+    #[doc = "Specify the foreground color in hexadecimal, e.g. FFB000"]
+    #[arg(long, value_parser = screen::parse_color)]
+    pub fg: Option<[u8; 3]>,
+
+    #[doc = "Specify the background color in hexadecimal"]
+    #[arg(long, value_parser = screen::parse_color)]
+    pub bg: Option<[u8; 3]>,
+
+    #[doc = "Specify the number of instructions executed per 60 Hz frame"]
+    #[arg(long, default_value_t = 11)]
+    pub ipf: u32,
+
+    #[doc = "Specify the beeper frequency in Hz"]
+    #[arg(long, default_value_t = 440.0)]
+    pub beep_frequency: f32,
 
+    #[doc = "Specify the beeper volume, from 0.0 to 1.0"]
+    #[arg(long, default_value_t = 0.25)]
+    pub volume: f32,
+
+    #[doc = "Specify the beeper waveform, either square or sine"]
+    #[arg(long, default_value = "square")]
+    pub waveform: Waveform,
+
+    #[doc = "Specify the quirks profile: vip, chip48, schip or modern"]
+    #[arg(long, default_value = "modern")]
+    pub quirks: QuirksPreset,
+
+    #[doc = "Specify the CHIP-8 variant: chip8, schip or xochip"]
+    #[arg(long, default_value = "chip8")]
+    pub variant: Variant,
+
+    #[doc = "Specify how many seconds can be rewound by holding Backspace, 0 disables rewinding"]
+    #[arg(long, default_value_t = 10)]
+    pub rewind_seconds: u32,
+
+    #[doc = "Specify the seed of the random number generator, by default it is seeded from the time"]
+    #[arg(long)]
+    pub seed: Option<u32>,
+
+    #[doc = "Record the keypad input of every frame to a movie file, movies need the window"]
+    #[arg(long, conflicts_with_all = ["replay", "headless", "debug", "gdb"])]
+    pub record: Option<String>,
+
+    #[doc = "Replay a movie file, its variant, quirks and speed override the options"]
+    #[arg(long, conflicts_with_all = ["debug", "gdb"])]
+    pub replay: Option<String>,
+
+    #[doc = "Exit once the replay is over, with a non-zero status if the screen doesn't match the recording"]
+    #[arg(long, requires = "replay", conflicts_with_all = ["debug", "gdb"], default_value_t = false)]
+    pub verify: bool,
+
+    #[doc = "Run without window, audio or input, for automated testing"]
+    #[arg(long, default_value_t = false)]
+    pub headless: bool,
+
+    #[doc = "Specify the number of frames of a headless run, it stops early if the program exits or loops on itself, replays run every frame of the movie"]
+    #[arg(long, default_value_t = 600, requires = "headless")]
+    pub frames: u32,
+
+    #[doc = "Write the screen at the end of a headless run to a file: .png for an image, .hash for its hash, ASCII art otherwise"]
+    #[arg(long, requires = "headless")]
+    pub dump_screen: Option<String>,
+
+    #[doc = "Run the rom step by step from a debugger prompt on the terminal"]
+    #[arg(long, default_value_t = false, conflicts_with = "headless")]
+    pub debug: bool,
+
+    #[doc = "Wait for a GDB connection on the given local port and let it drive the rom"]
+    #[arg(long, conflicts_with_all = ["headless", "debug"])]
+    pub gdb: Option<u16>,
+
+    #[doc = "Write a line per executed instruction to a file, with the registers it changed"]
+    #[arg(long)]
+    pub trace: Option<String>,
+
+    #[doc = "Only trace the instructions in an address range, e.g. 200-2FF"]
+    #[arg(long, requires = "trace")]
+    pub trace_range: Option<AddressRange>,
+
+    #[doc = "Keep only the last N lines of the trace, written when the emulator fails"]
+    #[arg(long, requires = "trace")]
+    pub trace_ring: Option<usize>,
+
+    #[doc = "Load keyboard and controller bindings from a keymap file, a game.keymap file next to the rom overrides it"]
+    #[arg(long)]
+    pub keymap: Option<String>
+}
 fn main() {
-    let configuration = AppConfiguration::parse();
+    match Cli::parse().command {
+        Command::Run(configuration) => run(configuration),
+        Command::Disasm(configuration) => disasm(configuration),
+        Command::Asm(configuration) => asm(configuration)
+    }
+}
 
-    let sdl = sdl2::init().expect("Failed to init SDL!");
-    let sdl_video = sdl.video().expect("Failed to init SDL Video!");
+fn asm(configuration: AsmConfiguration) {
+    match assembler::assemble_file(Path::new(&configuration.input)) {
+        Ok(rom) => std::fs::write(&configuration.output, rom).expect("Failed to write the rom!"),
+        Err(error) => {
+            eprintln!("error: {}", error);
+            std::process::exit(1);
+        }
+    }
+}
 
-    let mut event_pump = sdl.event_pump().expect("Failed to init SDL Event Pump!");
+fn disasm(configuration: DisasmConfiguration) {
+    let rom = std::fs::read(Path::new(&configuration.rom)).expect("Invalid rom path!");
 
-    let window = sdl_video.window("CHIP8 Emulator", configuration.width, configuration.height)
-    .allow_highdpi()
-    .resizable()
-    .build()
-    .expect("Failed to init SDL Window!");
+    print!("{}", disassembler::disassemble(&rom, PROGRAM_ADDRESS));
+}
+fn run(configuration: AppConfiguration) {
+    if configuration.headless {
+        return run_headless(configuration);
+    }
 
-    let mut window_canvas = window.into_canvas()
-    .present_vsync();
+    if configuration.debug {
+        return debug_console::run(configuration);
+    }
 
-    if configuration.hardware_canvas {
-        window_canvas = window_canvas.accelerated();
-    } else {
-        window_canvas = window_canvas.software();
+    if let Some(port) = configuration.gdb {
+        return run_gdb(configuration, port);
     }
 
-    let mut window_canvas = window_canvas.build()
-    .expect("Failed to create window canvas!");
+    #[cfg(feature = "sdl")]
+    frontend::run(configuration);
 
-    let mut emulator = Emulator::new(std::fs::read(Path::new::<String>(&configuration.rom)).expect("Invalid rom path!"));
+    #[cfg(not(feature = "sdl"))]
+    {
+        eprintln!("error: nauka was built without the sdl feature, only --headless, --debug and --gdb runs are available");
+        std::process::exit(1);
+    }
+}
 
-    let mut frame_calculator = FrameCalculator::new();
+#[doc = "Palette of the theme, overridden by --palette and then by --fg and --bg"]
+pub fn palette(configuration: &AppConfiguration) -> Palette {
+    let mut palette = configuration.palette.unwrap_or(configuration.theme.palette());
 
-    'run_loop: loop {
-        handle_input(&event_pump, &mut emulator);
+    if let Some(bg) = configuration.bg {
+        palette[0] = bg;
+    }
+    if let Some(fg) = configuration.fg {
+        palette[1] = fg;
+    }
 
-        if update(&mut event_pump, &mut emulator) == AppStatus::Exit {
-            break 'run_loop;
-        }
-        render(&mut window_canvas, &emulator);
+    palette
+}
+
+#[doc = "Emulator with the rom loaded, exits with an error if it doesn't fit in memory"]
+pub fn create_emulator(configuration: &AppConfiguration, variant: Variant, quirks: Quirks) -> Emulator {
+    let rom = std::fs::read(Path::new(&configuration.rom)).expect("Invalid rom path!");
 
-        if configuration.frame_calculator {
-            frame_calculator.tick();
-            println!("{}", frame_calculator.fps());
+    match Emulator::new(rom, variant, quirks) {
+        Ok(emulator) => emulator,
+        Err(error) => {
+            eprintln!("error: {}", error);
+            std::process::exit(1);
         }
     }
 }
 
-fn render(window_canvas: &mut Canvas<Window>, emulator: &Emulator) {
-    window_canvas.set_draw_color(sdl2::pixels::Color::RGB(0, 0, 0));
-    window_canvas.clear();
+#[doc = "Tracer asked for by --trace, if any"]
+pub fn create_tracer(configuration: &AppConfiguration) -> Option<Tracer> {
+    let path = configuration.trace.as_ref()?;
+    let file = File::create(Path::new(path)).expect("Failed to create the trace file!");
 
-    window_canvas.set_scale(window_canvas.window().size().0 as f32 / 64.0, window_canvas.window().size().1 as f32 / 32.0).expect("Failed to set SDL Window Canvas Scale!");
+    Some(Tracer::new(Box::new(BufWriter::new(file)), configuration.trace_range, configuration.trace_ring))
+}
+
+#[doc = "Movie file given to --replay, exits with an error if it can't be read"]
+pub fn load_movie(path: &Path) -> Movie {
+    let bytes = std::fs::read(path).expect("Invalid movie path!");
 
-    window_canvas.set_draw_color(sdl2::pixels::Color::RGB(255, 255, 255));
-    for (row_iteration, row) in emulator.video_memory().iter().enumerate() {
-        for (column_iteration, column) in row.iter().enumerate() {
-            if *column != false {
-                window_canvas.draw_point(sdl2::rect::Point::new(row_iteration as i32, column_iteration as i32)).expect("Failed to draw a Point!");
-            }
+    match Movie::from_bytes(&bytes) {
+        Ok(movie) => movie,
+        Err(error) => {
+            eprintln!("error: {}: {}", path.display(), error);
+            std::process::exit(1);
         }
     }
+}
+
+#[doc = "Check the movie was recorded with the loaded rom and seed the RNG like the recording"]
+pub fn start_replay(movie: &Movie, emulator: &mut Emulator) {
+    if let Err(error) = movie.check_rom(emulator.rom_hash()) {
+        eprintln!("error: {}", error);
+        std::process::exit(1);
+    }
 
-    window_canvas.present();
+    emulator.set_rng_seed(movie.seed);
 }
 
-fn update(event_pump: &mut EventPump, emulator: &mut Emulator) -> AppStatus {
-    for event in event_pump.poll_iter() {
-        match event {
-            sdl2::event::Event::Quit { timestamp: _ } => {
-                return AppStatus::Exit;
-            },
-            _ => {}
+#[doc = "Exit with a non-zero status unless the whole movie was replayed and the screen matches the recording"]
+pub fn verify_replay(movie: &Movie, emulator: &Emulator, frames_replayed: usize) {
+    if frames_replayed < movie.frames.len() {
+        eprintln!("Replay stopped after {} of {} frames", frames_replayed, movie.frames.len());
+        std::process::exit(1);
+    }
+
+    match movie.screen_hash {
+        Some(expected) if expected != emulator.screen_hash() => {
+            eprintln!("Replay desynced: screen hash {:016X}, expected {:016X}", emulator.screen_hash(), expected);
+            std::process::exit(1);
+        },
+        Some(_) => println!("Replay verified"),
+        None => {
+            eprintln!("The movie has no screen hash to verify");
+            std::process::exit(1);
         }
     }
+}
+
+fn run_headless(configuration: AppConfiguration) {
+    let replay = configuration.replay.as_ref().map(|path| load_movie(Path::new(path)));
+
+    // A replay has to run with the settings it was recorded with
+    let (variant, quirks, instructions_per_frame) = match &replay {
+        Some(movie) => (movie.variant, movie.quirks, movie.instructions_per_frame),
+        None => (configuration.variant, configuration.quirks.quirks(), configuration.ipf)
+    };
+
+    let mut emulator = create_emulator(&configuration, variant, quirks);
 
-    emulator.next_cycle();
+    if let Some(seed) = configuration.seed {
+        emulator.set_rng_seed(seed);
+    }
+
+    emulator.set_tracer(create_tracer(&configuration));
+
+    let (frames, stop_reason) = match &replay {
+        Some(movie) => {
+            start_replay(movie, &mut emulator);
+            headless::replay(&mut emulator, movie)
+        },
+        None => headless::run(&mut emulator, instructions_per_frame, configuration.frames)
+    };
+    println!("Stopped after {} frames: {}", frames, stop_reason);
+
+    if let Some(path) = &configuration.dump_screen {
+        dump_screen(Path::new(path), &emulator, &palette(&configuration));
+    }
 
-    AppStatus::Continue
+    // Flushes the trace before verify_replay() can exit
+    emulator.set_tracer(None);
+
+    if let (true, Some(movie)) = (configuration.verify, &replay) {
+        verify_replay(movie, &emulator, frames as usize);
+    }
+
+    if let StopReason::Crashed(_) = stop_reason {
+        std::process::exit(1);
+    }
 }
 
-fn handle_input(event_pump: &EventPump, emulator: &mut Emulator) {
-    let keyboard_state = event_pump.keyboard_state();
-    let scancodes: Vec<sdl2::keyboard::Scancode> = keyboard_state.pressed_scancodes().collect();
+fn run_gdb(configuration: AppConfiguration, port: u16) {
+    let mut emulator = create_emulator(&configuration, configuration.variant, configuration.quirks.quirks());
 
-    if !scancodes.is_empty() {
-        emulator.set_scancodes(scancodes);
+    if let Some(seed) = configuration.seed {
+        emulator.set_rng_seed(seed);
     }
+
+    emulator.set_tracer(create_tracer(&configuration));
+
+    let listener = TcpListener::bind(("127.0.0.1", port)).expect("Failed to listen for gdb!");
+    println!("Waiting for gdb on 127.0.0.1:{}", listener.local_addr().expect("Failed to listen for gdb!").port());
+
+    let (stream, _) = listener.accept().expect("Failed to accept the gdb connection!");
+    gdb_stub::serve(stream, &mut emulator, configuration.ipf).expect("Failed to talk to gdb!");
+}
+
+fn dump_screen(path: &Path, emulator: &Emulator, palette: &Palette) {
+    let contents = match path.extension().and_then(|extension| extension.to_str()) {
+        Some("png") => screen::to_png(emulator, palette),
+        Some("hash") => screen::to_hash(emulator).into_bytes(),
+        _ => screen::to_ascii(emulator).into_bytes()
+    };
+
+    std::fs::write(path, contents).expect("Failed to write the screen dump!");
 }
\ No newline at end of file
@@ -0,0 +1,220 @@
+use std::fmt;
+
+use crate::quirks::{MemoryIncrement, Quirks};
+use crate::variant::Variant;
+
+#[doc = "First bytes of every movie file"]
+pub const MAGIC: [u8; 4] = *b"C8MV";
+
+#[doc = "Version of the movie layout, bumped whenever it changes"]
+pub const VERSION: u16 = 1;
+
+const HEADER_SIZE: usize = 40;
+
+#[doc = "Recording of the keypad state of every frame, enough to replay a run bit for bit"]
+#[doc = ""]
+#[doc = "Stored little-endian as: magic `C8MV` (4), version (2), variant (1), quirk flags (1), memory increment quirk (1),"]
+#[doc = "screen hash flag (1), reserved (2), rom hash (8), RNG seed (4), instructions per frame (4), screen hash (8),"]
+#[doc = "frame count (4), then one 16 bit keypad mask per frame"]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    pub variant: Variant,
+    pub quirks: Quirks,
+    pub instructions_per_frame: u32,
+    pub rom_hash: u64,
+    pub seed: u32,
+    #[doc = "Keypad mask of every frame, as given to Keypad::from_mask"]
+    pub frames: Vec<u16>,
+    #[doc = "Emulator::screen_hash() after the last frame, checked when verifying a replay"]
+    pub screen_hash: Option<u64>,
+}
+
+#[doc = "Reason a movie couldn't be read"]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MovieError {
+    InvalidMagic,
+    UnsupportedVersion(u16),
+    Truncated,
+    InvalidVariant(u8),
+    InvalidQuirks,
+    RomMismatch { expected: u64, found: u64 },
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MovieError::InvalidMagic => write!(f, "not a movie"),
+            MovieError::UnsupportedVersion(version) => write!(f, "unsupported movie version {}", version),
+            MovieError::Truncated => write!(f, "movie is truncated"),
+            MovieError::InvalidVariant(id) => write!(f, "movie has an unknown variant {}", id),
+            MovieError::InvalidQuirks => write!(f, "movie has invalid quirks"),
+            MovieError::RomMismatch { expected, found } => write!(f, "movie was recorded with rom {:016X}, not {:016X}", found, expected),
+        }
+    }
+}
+
+impl std::error::Error for MovieError {}
+
+impl Movie {
+    #[doc = "Start an empty recording"]
+    pub fn new(variant: Variant, quirks: Quirks, instructions_per_frame: u32, rom_hash: u64, seed: u32) -> Self {
+        Self {
+            variant,
+            quirks,
+            instructions_per_frame,
+            rom_hash,
+            seed,
+            frames: Vec::new(),
+            screen_hash: None,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_SIZE + self.frames.len() * 2);
+
+        bytes.extend_from_slice(&MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.push(self.variant.id());
+        bytes.push(quirk_flags(&self.quirks));
+        bytes.push(match self.quirks.memory_increment {
+            MemoryIncrement::Unchanged => 0,
+            MemoryIncrement::ByX => 1,
+            MemoryIncrement::ByXPlusOne => 2,
+        });
+        bytes.push(self.screen_hash.is_some() as u8);
+        bytes.extend_from_slice(&[0; 2]);
+        bytes.extend_from_slice(&self.rom_hash.to_le_bytes());
+        bytes.extend_from_slice(&self.seed.to_le_bytes());
+        bytes.extend_from_slice(&self.instructions_per_frame.to_le_bytes());
+        bytes.extend_from_slice(&self.screen_hash.unwrap_or(0).to_le_bytes());
+        bytes.extend_from_slice(&(self.frames.len() as u32).to_le_bytes());
+        self.frames.iter().for_each(|mask| bytes.extend_from_slice(&mask.to_le_bytes()));
+
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, MovieError> {
+        if bytes.len() < HEADER_SIZE {
+            return Err(if bytes.starts_with(&MAGIC) { MovieError::Truncated } else { MovieError::InvalidMagic });
+        }
+
+        if bytes[0..4] != MAGIC {
+            return Err(MovieError::InvalidMagic);
+        }
+
+        let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+        if version != VERSION {
+            return Err(MovieError::UnsupportedVersion(version));
+        }
+
+        let variant = Variant::from_id(bytes[6]).ok_or(MovieError::InvalidVariant(bytes[6]))?;
+        let memory_increment = match bytes[8] {
+            0 => MemoryIncrement::Unchanged,
+            1 => MemoryIncrement::ByX,
+            2 => MemoryIncrement::ByXPlusOne,
+            _ => return Err(MovieError::InvalidQuirks),
+        };
+        let quirks = Quirks {
+            shift_uses_vy: bytes[7] & 0x01 != 0,
+            memory_increment,
+            jump_uses_vx: bytes[7] & 0x02 != 0,
+            logic_resets_vf: bytes[7] & 0x04 != 0,
+            sprites_wrap: bytes[7] & 0x08 != 0,
+            display_wait: bytes[7] & 0x10 != 0,
+        };
+
+        let u32_at = |offset: usize| u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]]);
+        let u64_at = |offset: usize| (u32_at(offset) as u64) | ((u32_at(offset + 4) as u64) << 32);
+
+        let frame_count = u32_at(36) as usize;
+        let frame_bytes = &bytes[HEADER_SIZE..];
+        if frame_bytes.len() < frame_count * 2 {
+            return Err(MovieError::Truncated);
+        }
+
+        Ok(Self {
+            variant,
+            quirks,
+            instructions_per_frame: u32_at(24),
+            rom_hash: u64_at(12),
+            seed: u32_at(20),
+            frames: frame_bytes.chunks_exact(2).take(frame_count).map(|mask| u16::from_le_bytes([mask[0], mask[1]])).collect(),
+            screen_hash: if bytes[9] != 0 { Some(u64_at(28)) } else { None },
+        })
+    }
+
+    #[doc = "Fails unless the movie was recorded with the rom of the given hash"]
+    pub fn check_rom(&self, rom_hash: u64) -> Result<(), MovieError> {
+        if self.rom_hash != rom_hash {
+            return Err(MovieError::RomMismatch { expected: rom_hash, found: self.rom_hash });
+        }
+
+        Ok(())
+    }
+}
+
+fn quirk_flags(quirks: &Quirks) -> u8 {
+    (quirks.shift_uses_vy as u8)
+        | (quirks.jump_uses_vx as u8) << 1
+        | (quirks.logic_resets_vf as u8) << 2
+        | (quirks.sprites_wrap as u8) << 3
+        | (quirks.display_wait as u8) << 4
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Emulator;
+
+    fn recording() -> Movie {
+        let mut movie = Movie::new(Variant::SuperChip, Quirks::super_chip(), 15, 0x0123_4567_89AB_CDEF, 1234);
+        movie.frames = vec![0x0000, 0x0010, 0x8001, 0xFFFF];
+        movie.screen_hash = Some(0xFEDC_BA98_7654_3210);
+        movie
+    }
+
+    #[test]
+    fn round_trip() {
+        let movie = recording();
+        let bytes = movie.to_bytes();
+
+        assert_eq!(&bytes[..4], &MAGIC);
+        assert_eq!(bytes.len(), HEADER_SIZE + movie.frames.len() * 2);
+        assert_eq!(Movie::from_bytes(&bytes), Ok(movie));
+
+        let empty = Movie::new(Variant::XoChip, Quirks::cosmac_vip(), 1000, 0, 0);
+        assert_eq!(Movie::from_bytes(&empty.to_bytes()), Ok(empty));
+    }
+
+    #[test]
+    fn rejects_truncated_frames() {
+        let bytes = recording().to_bytes();
+
+        for length in [4, HEADER_SIZE - 1, HEADER_SIZE, bytes.len() - 1] {
+            assert_eq!(Movie::from_bytes(&bytes[..length]), Err(MovieError::Truncated), "movie cut to {} bytes", length);
+        }
+    }
+
+    #[test]
+    fn rejects_other_versions_and_files() {
+        let mut bytes = recording().to_bytes();
+        bytes[4..6].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        assert_eq!(Movie::from_bytes(&bytes), Err(MovieError::UnsupportedVersion(VERSION + 1)));
+
+        bytes[..4].copy_from_slice(b"C8SS");
+        assert_eq!(Movie::from_bytes(&bytes), Err(MovieError::InvalidMagic));
+        assert_eq!(Movie::from_bytes(b"C8"), Err(MovieError::InvalidMagic));
+    }
+
+    #[test]
+    fn rejects_another_rom() {
+        let emulator = Emulator::new(vec![0x12, 0x00], Variant::SuperChip, Quirks::super_chip()).unwrap();
+        let mut movie = recording();
+
+        assert_eq!(movie.check_rom(emulator.rom_hash()), Err(MovieError::RomMismatch { expected: emulator.rom_hash(), found: movie.rom_hash }));
+
+        movie.rom_hash = emulator.rom_hash();
+        let movie = Movie::from_bytes(&movie.to_bytes()).unwrap();
+        assert_eq!(movie.check_rom(emulator.rom_hash()), Ok(()));
+    }
+}
@@ -1,3 +1,6 @@
+// num-derive 0.4 expands FromPrimitive inside an anonymous const, which newer rustc flags
+#![allow(non_local_definitions)]
+
 use num_derive::FromPrimitive;
 
 #[repr(u16)]
@@ -25,7 +28,25 @@ pub enum Opcode {
 #[derive(Debug, FromPrimitive)]
 pub enum ZeroOpcode {
     CLS = 0xE0,
-    RET = 0xEE
+    RET = 0xEE,
+    // SUPER-CHIP, SCD carries the scroll amount in its lowest nibble
+    SCD = 0xC0,
+    // XO-CHIP, SCU carries the scroll amount in its lowest nibble
+    SCU = 0xD0,
+    SCR = 0xFB,
+    SCL = 0xFC,
+    EXIT = 0xFD,
+    LOW = 0xFE,
+    HIGH = 0xFF
+}
+
+#[repr(u16)]
+#[derive(Debug, FromPrimitive)]
+pub enum FiveOpcode {
+    SeVxVy = 0x0,
+    // XO-CHIP
+    SaveVxVy = 0x2,
+    LoadVxVy = 0x3,
 }
 
 #[repr(u16)]
@@ -52,13 +73,24 @@ pub enum FourteenOpcode {
 #[repr(u16)]
 #[derive(Debug, FromPrimitive)]
 pub enum FifteenOpcode {
+    // XO-CHIP, followed by a 16 bit address
+    LdILong = 0x00,
+    // XO-CHIP, carries the plane mask in place of X
+    Plane = 0x01,
+    // XO-CHIP
+    Audio = 0x02,
     LdVxDt = 0x07,
     LdVxK = 0x0A,
     LdDtVx = 0x15,
     LdStVx = 0x18,
     AddIVx = 0x1E,
     LdFVx = 0x29,
+    LdHfVx = 0x30,
     LdBVx = 0x33,
+    // XO-CHIP
+    PitchVx = 0x3A,
     LdIVx = 0x55,
     LdVxI = 0x65,
+    LdRVx = 0x75,
+    LdVxR = 0x85,
 }
\ No newline at end of file
@@ -0,0 +1,116 @@
+use std::str::FromStr;
+
+#[doc = "How FX55 / FX65 leave the I register after accessing memory"]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryIncrement {
+    #[doc = "I is left untouched (SUPER-CHIP 1.1)"]
+    Unchanged,
+    #[doc = "I is incremented by X (CHIP-48)"]
+    ByX,
+    #[doc = "I is incremented by X + 1 (COSMAC VIP)"]
+    ByXPlusOne,
+}
+
+#[doc = "Behaviors that differ between CHIP-8 interpreters"]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    #[doc = "8XY6 / 8XYE shift VY into VX instead of shifting VX in place"]
+    pub shift_uses_vy: bool,
+    #[doc = "Effect of FX55 / FX65 on I"]
+    pub memory_increment: MemoryIncrement,
+    #[doc = "BNNN jumps to XNN + VX instead of NNN + V0"]
+    pub jump_uses_vx: bool,
+    #[doc = "8XY1 / 8XY2 / 8XY3 reset VF to 0"]
+    pub logic_resets_vf: bool,
+    #[doc = "Sprites crossing the screen edge wrap around instead of being clipped"]
+    pub sprites_wrap: bool,
+    #[doc = "DXYN waits for the next frame before the program continues"]
+    pub display_wait: bool,
+}
+
+impl Quirks {
+    pub fn cosmac_vip() -> Self {
+        Self {
+            shift_uses_vy: true,
+            memory_increment: MemoryIncrement::ByXPlusOne,
+            jump_uses_vx: false,
+            logic_resets_vf: true,
+            sprites_wrap: false,
+            display_wait: true,
+        }
+    }
+
+    pub fn chip48() -> Self {
+        Self {
+            shift_uses_vy: false,
+            memory_increment: MemoryIncrement::ByX,
+            jump_uses_vx: true,
+            logic_resets_vf: false,
+            sprites_wrap: false,
+            display_wait: false,
+        }
+    }
+
+    pub fn super_chip() -> Self {
+        Self {
+            shift_uses_vy: false,
+            memory_increment: MemoryIncrement::Unchanged,
+            jump_uses_vx: true,
+            logic_resets_vf: false,
+            sprites_wrap: false,
+            display_wait: false,
+        }
+    }
+
+    #[doc = "Behavior of modern interpreters such as Octo"]
+    pub fn modern() -> Self {
+        Self {
+            shift_uses_vy: true,
+            memory_increment: MemoryIncrement::ByXPlusOne,
+            jump_uses_vx: false,
+            logic_resets_vf: false,
+            sprites_wrap: true,
+            display_wait: false,
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Self::modern()
+    }
+}
+
+#[doc = "Named quirks profile, parsed from vip, chip48, schip or modern"]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuirksPreset {
+    CosmacVip,
+    Chip48,
+    SuperChip,
+    Modern,
+}
+
+impl QuirksPreset {
+    pub fn quirks(&self) -> Quirks {
+        match self {
+            QuirksPreset::CosmacVip => Quirks::cosmac_vip(),
+            QuirksPreset::Chip48 => Quirks::chip48(),
+            QuirksPreset::SuperChip => Quirks::super_chip(),
+            QuirksPreset::Modern => Quirks::modern(),
+        }
+    }
+}
+
+impl FromStr for QuirksPreset {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "vip" | "cosmac-vip" => Ok(QuirksPreset::CosmacVip),
+            "chip48" | "chip-48" => Ok(QuirksPreset::Chip48),
+            "schip" | "super-chip" => Ok(QuirksPreset::SuperChip),
+            "modern" => Ok(QuirksPreset::Modern),
+            _ => Err(format!("unknown quirks profile '{}', expected 'vip', 'chip48', 'schip' or 'modern'", value)),
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use nauka::emulator::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use nauka::screen::{self, Palette, ScaleFilter, Theme};
+use nauka::Emulator;
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
+use sdl2::render::{Canvas, Texture, TextureCreator};
+use sdl2::video::{Window, WindowContext};
+
+#[doc = "Background of a crashed program"]
+const CRASH_BACKGROUND: [u8; 3] = [128, 0, 0];
+
+#[doc = "Streaming textures the screen is uploaded to every frame, one per resolution, scaled to the window when copied"]
+pub struct Renderer<'a> {
+    lores: Texture<'a>,
+    hires: Texture<'a>,
+    integer_scaling: bool,
+    theme: Theme,
+    palette: Palette,
+}
+
+impl<'a> Renderer<'a> {
+    #[doc = "The palette is the one of the theme, unless colors were customized"]
+    pub fn new(texture_creator: &'a TextureCreator<WindowContext>, filter: ScaleFilter, integer_scaling: bool, theme: Theme, palette: Palette) -> Result<Self, String> {
+        // The hint is read when textures are created
+        let quality = match filter {
+            ScaleFilter::Nearest => "nearest",
+            ScaleFilter::Linear => "linear",
+        };
+        sdl2::hint::set("SDL_RENDER_SCALE_QUALITY", quality);
+
+        let create = |width: usize, height: usize| {
+            texture_creator.create_texture_streaming(PixelFormatEnum::RGB24, width as u32, height as u32).map_err(|error| error.to_string())
+        };
+
+        Ok(Self {
+            lores: create(SCREEN_WIDTH / 2, SCREEN_HEIGHT / 2)?,
+            hires: create(SCREEN_WIDTH, SCREEN_HEIGHT)?,
+            integer_scaling,
+            theme,
+            palette,
+        })
+    }
+
+    #[doc = "Switch to the palette of the next built-in theme, custom colors are dropped"]
+    pub fn next_theme(&mut self) -> Theme {
+        self.theme = self.theme.next();
+        self.palette = self.theme.palette();
+        self.theme
+    }
+
+    #[doc = "Draw the screen letterboxed in black, on a red background once the program crashed"]
+    pub fn render(&mut self, canvas: &mut Canvas<Window>, emulator: &Emulator, crashed: bool) -> Result<(), String> {
+        let mut palette = self.palette;
+        if crashed {
+            palette[0] = CRASH_BACKGROUND;
+        }
+
+        let (width, height) = emulator.resolution();
+        let texture = if width == SCREEN_WIDTH { &mut self.hires } else { &mut self.lores };
+        let video_memory = emulator.video_memory();
+
+        texture.with_lock(None, |buffer, pitch| {
+            for y in 0..height {
+                for (x, column) in video_memory[..width].iter().enumerate() {
+                    let offset = y * pitch + x * 3;
+
+                    buffer[offset..offset + 3].copy_from_slice(&palette[column[y] as usize % palette.len()]);
+                }
+            }
+        })?;
+
+        let (x, y, viewport_width, viewport_height) = screen::viewport(canvas.output_size()?, (width as u32, height as u32), self.integer_scaling);
+
+        canvas.set_draw_color(Color::RGB(0, 0, 0));
+        canvas.clear();
+        canvas.copy(texture, None, Rect::new(x, y, viewport_width, viewport_height))?;
+        canvas.present();
+
+        Ok(())
+    }
+}
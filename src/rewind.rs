@@ -0,0 +1,220 @@
+use std::collections::VecDeque;
+
+#[doc = "Ring buffer of save states used to run the emulation backwards"]
+#[doc = ""]
+#[doc = "Only the newest state is stored as is, every older one is kept as the XOR of itself and its newer neighbour,"]
+#[doc = "run-length encoded, so consecutive frames only cost the bytes that changed between them"]
+#[derive(Debug, Clone)]
+pub struct RewindBuffer {
+    capacity: usize,
+    newest: Option<Vec<u8>>,
+    #[doc = "Deltas from the oldest at the front to the one right before the newest state at the back"]
+    deltas: VecDeque<Vec<u8>>,
+}
+
+impl RewindBuffer {
+    #[doc = "Create a buffer keeping at most the given number of states"]
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            newest: None,
+            deltas: VecDeque::new(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.deltas.len() + self.newest.is_some() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.newest.is_none()
+    }
+
+    pub fn clear(&mut self) {
+        self.newest = None;
+        self.deltas.clear();
+    }
+
+    #[doc = "Store a state, dropping the oldest one once the buffer is full"]
+    pub fn push(&mut self, state: Vec<u8>) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if let Some(newest) = self.newest.take() {
+            self.deltas.push_back(encode_delta(&newest, &state));
+        }
+        self.newest = Some(state);
+
+        while self.len() > self.capacity {
+            self.deltas.pop_front();
+        }
+    }
+
+    #[doc = "Drop the newest state and return the one before it, which becomes the newest"]
+    #[doc = ""]
+    #[doc = "Returns None once only the oldest state is left"]
+    pub fn rewind(&mut self) -> Option<Vec<u8>> {
+        let delta = self.deltas.pop_back()?;
+        let newest = self.newest.take()?;
+        let previous = decode_delta(&newest, &delta);
+
+        self.newest = Some(previous.clone());
+        Some(previous)
+    }
+}
+
+#[doc = "XOR of the old and new state, with the old length in front in case the lengths differ"]
+fn encode_delta(old: &[u8], new: &[u8]) -> Vec<u8> {
+    let xor: Vec<u8> = (0..old.len()).map(|i| old[i] ^ new.get(i).copied().unwrap_or(0)).collect();
+
+    let mut delta = (old.len() as u32).to_le_bytes().to_vec();
+    delta.extend(run_length_encode(&xor));
+    delta
+}
+
+fn decode_delta(new: &[u8], delta: &[u8]) -> Vec<u8> {
+    let length = u32::from_le_bytes([delta[0], delta[1], delta[2], delta[3]]) as usize;
+    let xor = run_length_decode(&delta[4..]);
+
+    (0..length).map(|i| xor[i] ^ new.get(i).copied().unwrap_or(0)).collect()
+}
+
+#[doc = "Encode the bytes as chunks starting with a control byte:"]
+#[doc = "0x80 | (n - 1) stands for n zero bytes, n - 1 for n literal bytes following it, n going up to 128"]
+fn run_length_encode(bytes: &[u8]) -> Vec<u8> {
+    let mut encoded = Vec::new();
+    let mut position = 0;
+
+    while position < bytes.len() {
+        let zeroes = bytes[position..].iter().take(128).take_while(|byte| **byte == 0).count();
+
+        if zeroes > 0 {
+            encoded.push(0x80 | (zeroes - 1) as u8);
+            position += zeroes;
+        } else {
+            let literals = bytes[position..].iter().take(128).take_while(|byte| **byte != 0).count();
+
+            encoded.push((literals - 1) as u8);
+            encoded.extend_from_slice(&bytes[position..position + literals]);
+            position += literals;
+        }
+    }
+
+    encoded
+}
+
+fn run_length_decode(encoded: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let mut position = 0;
+
+    while position < encoded.len() {
+        let control = encoded[position];
+        let length = (control & 0x7F) as usize + 1;
+        position += 1;
+
+        if control & 0x80 != 0 {
+            bytes.resize(bytes.len() + length, 0);
+        } else {
+            bytes.extend_from_slice(&encoded[position..position + length]);
+            position += length;
+        }
+    }
+
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[doc = "State of the given length filled with a pattern that changes with the seed"]
+    fn state(length: usize, seed: u8) -> Vec<u8> {
+        (0..length).map(|i| if i % 7 == 0 { seed } else { (i / 3) as u8 }).collect()
+    }
+
+    #[test]
+    fn run_length_round_trip() {
+        let long_literal: Vec<u8> = (1..=300).map(|i| (i % 255 + 1) as u8).collect();
+        let cases: [Vec<u8>; 6] = [
+            Vec::new(),
+            vec![0],
+            vec![0; 300],
+            long_literal,
+            vec![1, 0, 0, 2, 3, 0, 4],
+            [vec![0; 129], vec![5; 129], vec![0; 1]].concat(),
+        ];
+
+        for bytes in cases.iter() {
+            assert_eq!(&run_length_decode(&run_length_encode(bytes)), bytes);
+        }
+    }
+
+    #[test]
+    fn run_length_chunks() {
+        assert_eq!(run_length_encode(&[0; 3]), [0x82]);
+        assert_eq!(run_length_encode(&[0; 130]), [0xFF, 0x81]);
+        assert_eq!(run_length_encode(&[7, 8, 0, 9]), [0x01, 7, 8, 0x80, 0x00, 9]);
+    }
+
+    #[test]
+    fn delta_round_trip() {
+        let old = state(64, 1);
+        let new = state(64, 2);
+
+        let delta = encode_delta(&old, &new);
+        assert_eq!(decode_delta(&new, &delta), old);
+
+        // Identical states only cost the length and a few zero runs
+        assert!(encode_delta(&new, &new).len() <= 4 + 1);
+    }
+
+    #[test]
+    fn delta_between_different_lengths() {
+        let short = state(10, 3);
+        let long = state(300, 4);
+
+        assert_eq!(decode_delta(&long, &encode_delta(&short, &long)), short);
+        assert_eq!(decode_delta(&short, &encode_delta(&long, &short)), long);
+        assert_eq!(decode_delta(&long, &encode_delta(&[], &long)), Vec::<u8>::new());
+        assert_eq!(decode_delta(&[], &encode_delta(&long, &[])), long);
+    }
+
+    #[test]
+    fn rewinds_in_order() {
+        let states: Vec<Vec<u8>> = (0..5).map(|frame| state(20 + frame * 5, frame as u8)).collect();
+
+        let mut buffer = RewindBuffer::new(10);
+        for state in states.iter() {
+            buffer.push(state.clone());
+        }
+        assert_eq!(buffer.len(), 5);
+
+        for expected in states.iter().rev().skip(1) {
+            assert_eq!(buffer.rewind().as_ref(), Some(expected));
+        }
+        assert_eq!(buffer.rewind(), None);
+        assert_eq!(buffer.len(), 1);
+    }
+
+    #[test]
+    fn drops_the_oldest_states() {
+        let mut buffer = RewindBuffer::new(3);
+        for frame in 0..6 {
+            buffer.push(state(16, frame));
+        }
+        assert_eq!(buffer.len(), 3);
+
+        assert_eq!(buffer.rewind(), Some(state(16, 4)));
+        assert_eq!(buffer.rewind(), Some(state(16, 3)));
+        assert_eq!(buffer.rewind(), None);
+
+        let mut disabled = RewindBuffer::new(0);
+        disabled.push(state(16, 0));
+        assert!(disabled.is_empty());
+    }
+}
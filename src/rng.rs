@@ -0,0 +1,84 @@
+#[doc = "Source of the random bytes returned by RND, swappable to make runs reproducible"]
+pub trait RandomSource {
+    fn next_u8(&mut self) -> u8;
+
+    #[doc = "State stored in save states, set_state(state()) has to resume the same sequence"]
+    fn state(&self) -> u32;
+
+    fn set_state(&mut self, state: u32);
+}
+
+#[doc = "Small xorshift pseudo-random number generator used by the RND instruction"]
+#[derive(Debug, Clone)]
+pub struct XorShift {
+    state: u32,
+}
+
+impl XorShift {
+    pub fn new(seed: u32) -> Self {
+        // Xorshift gets stuck on a zero state
+        Self { state: if seed == 0 { 0x9E37_79B9 } else { seed } }
+    }
+
+    #[doc = "Seed the generator from the current system time"]
+    pub fn from_time() -> Self {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|duration| duration.subsec_nanos() ^ duration.as_secs() as u32)
+            .unwrap_or(0);
+
+        Self::new(nanos)
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.state = x;
+        x
+    }
+}
+
+impl RandomSource for XorShift {
+    fn next_u8(&mut self) -> u8 {
+        (self.next_u32() >> 24) as u8
+    }
+
+    fn state(&self) -> u32 {
+        self.state
+    }
+
+    fn set_state(&mut self, state: u32) {
+        *self = Self::new(state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Emulator, Quirks, Variant};
+
+    #[doc = "Bytes returned by RND V0, 0xFF over a few loops of an emulator seeded with the given seed"]
+    fn random_bytes(seed: u32) -> Vec<u8> {
+        // RND V0, 0xFF / JP 0x200
+        let mut emulator = Emulator::new(vec![0xC0, 0xFF, 0x12, 0x00], Variant::Chip8, Quirks::default()).unwrap();
+        emulator.set_rng_seed(seed);
+
+        (0..32)
+            .map(|_| {
+                emulator.next_cycle().unwrap();
+                emulator.next_cycle().unwrap();
+                emulator.registers()[0]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn same_seed_same_sequence() {
+        assert_eq!(random_bytes(1234), random_bytes(1234));
+        assert_eq!(random_bytes(1234)[0], XorShift::new(1234).next_u8());
+        assert_eq!(random_bytes(0), random_bytes(0));
+        assert_ne!(random_bytes(1234), random_bytes(1235));
+    }
+}
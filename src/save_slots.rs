@@ -0,0 +1,57 @@
+use std::path::{Path, PathBuf};
+
+use nauka::Emulator;
+
+const SLOT_COUNT: u8 = 10;
+
+#[doc = "Numbered save state files next to the rom, `game.ch8` uses `game.s0` to `game.s9`"]
+pub struct SaveSlots {
+    rom_path: PathBuf,
+    slot: u8,
+    loading_enabled: bool,
+}
+
+impl SaveSlots {
+    pub fn new(rom_path: &Path) -> Self {
+        Self {
+            rom_path: rom_path.to_path_buf(),
+            slot: 0,
+            loading_enabled: true,
+        }
+    }
+
+    pub fn slot(&self) -> u8 {
+        self.slot
+    }
+
+    pub fn path(&self) -> PathBuf {
+        self.rom_path.with_extension(format!("s{}", self.slot))
+    }
+
+    pub fn next(&mut self) {
+        self.slot = (self.slot + 1) % SLOT_COUNT;
+    }
+
+    pub fn previous(&mut self) {
+        self.slot = (self.slot + SLOT_COUNT - 1) % SLOT_COUNT;
+    }
+
+    pub fn save(&self, emulator: &Emulator) -> Result<(), String> {
+        std::fs::write(self.path(), emulator.save_state()).map_err(|error| error.to_string())
+    }
+
+    #[doc = "Make load() fail, saving still works"]
+    pub fn disable_loading(&mut self) {
+        self.loading_enabled = false;
+    }
+
+    pub fn load(&self, emulator: &mut Emulator) -> Result<(), String> {
+        if !self.loading_enabled {
+            return Err("loading states is disabled while a movie is recorded or replayed".to_string());
+        }
+
+        let state = std::fs::read(self.path()).map_err(|error| error.to_string())?;
+
+        emulator.load_state(&state).map_err(|error| error.to_string())
+    }
+}
@@ -0,0 +1,247 @@
+use std::fmt;
+
+use crate::variant::Variant;
+
+#[doc = "First bytes of every save state file"]
+pub const MAGIC: [u8; 4] = *b"C8ST";
+
+#[doc = "Version of the save state layout, bumped whenever the payload changes"]
+pub const VERSION: u16 = 1;
+
+#[doc = "Size of the header preceding the payload"]
+pub const HEADER_SIZE: usize = 24;
+
+#[doc = "Header of a save state, all values are little-endian:"]
+#[doc = ""]
+#[doc = "| offset | size | field                          |"]
+#[doc = "|--------|------|--------------------------------|"]
+#[doc = "| 0      | 4    | magic, `C8ST`                  |"]
+#[doc = "| 4      | 2    | format version                 |"]
+#[doc = "| 6      | 1    | variant (0 CHIP-8, 1 SUPER-CHIP, 2 XO-CHIP) |"]
+#[doc = "| 7      | 1    | reserved, 0                    |"]
+#[doc = "| 8      | 8    | FNV-1a hash of the rom         |"]
+#[doc = "| 16     | 4    | payload length                 |"]
+#[doc = "| 20     | 4    | CRC-32 of the payload          |"]
+#[doc = ""]
+#[doc = "The payload holds, in order: pc (2), i (2), sp (1), V0-VF (16), stack (16 x 2), delay and sound timers (2),"]
+#[doc = "hi-res flag (1), selected planes (1), pitch (1), waiting key (1, 0xFF if none), audio pattern flag (1) and pattern (16),"]
+#[doc = "RPL flags (16), RNG state (4), memory length (4) and memory, and the 128x64 video memory stored column by column"]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SaveStateHeader {
+    pub version: u16,
+    pub variant: Variant,
+    pub rom_hash: u64,
+    pub payload_length: u32,
+    pub checksum: u32,
+}
+
+#[doc = "Reason a save state couldn't be loaded"]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SaveStateError {
+    InvalidMagic,
+    UnsupportedVersion(u16),
+    Truncated,
+    ChecksumMismatch,
+    VariantMismatch { expected: Variant, found: Variant },
+    RomMismatch { expected: u64, found: u64 },
+    #[doc = "The checksum is fine but a value is out of its valid range"]
+    InvalidPayload(&'static str),
+}
+
+impl fmt::Display for SaveStateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaveStateError::InvalidMagic => write!(f, "not a save state"),
+            SaveStateError::UnsupportedVersion(version) => write!(f, "unsupported save state version {}", version),
+            SaveStateError::Truncated => write!(f, "save state is truncated"),
+            SaveStateError::ChecksumMismatch => write!(f, "save state is corrupted"),
+            SaveStateError::VariantMismatch { expected, found } => write!(f, "save state is for {:?}, not {:?}", found, expected),
+            SaveStateError::RomMismatch { expected, found } => write!(f, "save state is for rom {:016X}, not {:016X}", found, expected),
+            SaveStateError::InvalidPayload(field) => write!(f, "save state has an invalid {}", field),
+        }
+    }
+}
+
+impl std::error::Error for SaveStateError {}
+
+impl SaveStateHeader {
+    pub fn write(&self, output: &mut Vec<u8>) {
+        output.extend_from_slice(&MAGIC);
+        output.extend_from_slice(&self.version.to_le_bytes());
+        output.push(self.variant.id());
+        output.push(0);
+        output.extend_from_slice(&self.rom_hash.to_le_bytes());
+        output.extend_from_slice(&self.payload_length.to_le_bytes());
+        output.extend_from_slice(&self.checksum.to_le_bytes());
+    }
+
+    pub fn read(reader: &mut StateReader) -> Result<Self, SaveStateError> {
+        if reader.bytes(4)? != MAGIC {
+            return Err(SaveStateError::InvalidMagic);
+        }
+
+        let version = reader.u16()?;
+        if version != VERSION {
+            return Err(SaveStateError::UnsupportedVersion(version));
+        }
+
+        let variant = Variant::from_id(reader.u8()?).ok_or(SaveStateError::InvalidPayload("variant"))?;
+        reader.u8()?;
+
+        Ok(Self {
+            version,
+            variant,
+            rom_hash: reader.u64()?,
+            payload_length: reader.u32()?,
+            checksum: reader.u32()?,
+        })
+    }
+}
+
+#[doc = "Little-endian cursor over a save state, every read fails with Truncated past the end"]
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    pub fn bytes(&mut self, length: usize) -> Result<&'a [u8], SaveStateError> {
+        let end = self.position.checked_add(length).ok_or(SaveStateError::Truncated)?;
+        let bytes = self.data.get(self.position..end).ok_or(SaveStateError::Truncated)?;
+
+        self.position = end;
+        Ok(bytes)
+    }
+
+    pub fn array<const N: usize>(&mut self) -> Result<[u8; N], SaveStateError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.bytes(N)?);
+        Ok(array)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, SaveStateError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, SaveStateError> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, SaveStateError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, SaveStateError> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Emulator, Quirks};
+
+    // LD V1, 0x2A / LD F, V1 / DRW V0, V0, 5 / CALL 0x20A / JP 0x208 / RET
+    const ROM: [u8; 12] = [0x61, 0x2A, 0xF1, 0x29, 0xD0, 0x05, 0x22, 0x0A, 0x12, 0x08, 0x00, 0xEE];
+
+    fn emulator(rom: &[u8], variant: Variant) -> Emulator {
+        let mut emulator = Emulator::new(rom.to_vec(), variant, Quirks::default()).unwrap();
+        emulator.set_rng_seed(1234);
+        emulator
+    }
+
+    #[doc = "State of the rom after a few instructions, with a sprite drawn and a return address on the stack"]
+    fn running_state() -> Vec<u8> {
+        let mut emulator = emulator(&ROM, Variant::Chip8);
+        for _ in 0..4 {
+            emulator.next_cycle().unwrap();
+        }
+
+        emulator.save_state()
+    }
+
+    fn load(state: &[u8], variant: Variant) -> (Emulator, Result<(), SaveStateError>) {
+        let mut emulator = emulator(&ROM, variant);
+        let result = emulator.load_state(state);
+
+        (emulator, result)
+    }
+
+    #[test]
+    fn round_trip() {
+        let state = running_state();
+
+        let (emulator, result) = load(&state, Variant::Chip8);
+        assert_eq!(result, Ok(()));
+        assert_eq!(emulator.pc(), 0x20A);
+        assert_eq!(emulator.sp(), 1);
+        assert_eq!(emulator.registers()[1], 0x2A);
+        assert_ne!(emulator.screen_hash(), self::emulator(&ROM, Variant::Chip8).screen_hash());
+        assert_eq!(emulator.save_state(), state);
+    }
+
+    #[test]
+    fn header_layout() {
+        let state = running_state();
+        let header = SaveStateHeader::read(&mut StateReader::new(&state)).unwrap();
+
+        assert_eq!(&state[..4], &MAGIC);
+        assert_eq!(header.version, VERSION);
+        assert_eq!(header.variant, Variant::Chip8);
+        assert_eq!(header.payload_length as usize, state.len() - HEADER_SIZE);
+    }
+
+    #[test]
+    fn rejects_another_rom() {
+        let state = running_state();
+
+        let mut other = emulator(&[0x12, 0x00], Variant::Chip8);
+        assert!(matches!(other.load_state(&state), Err(SaveStateError::RomMismatch { .. })));
+        assert_eq!(other.pc(), 0x200);
+    }
+
+    #[test]
+    fn rejects_another_variant() {
+        let (emulator, result) = load(&running_state(), Variant::SuperChip);
+
+        assert_eq!(result, Err(SaveStateError::VariantMismatch { expected: Variant::SuperChip, found: Variant::Chip8 }));
+        assert_eq!(emulator.pc(), 0x200);
+    }
+
+    #[test]
+    fn rejects_a_bad_checksum() {
+        let mut state = running_state();
+        let last = state.len() - 1;
+        state[last] ^= 0x01;
+
+        let (emulator, result) = load(&state, Variant::Chip8);
+        assert_eq!(result, Err(SaveStateError::ChecksumMismatch));
+        assert_eq!(emulator.pc(), 0x200);
+    }
+
+    #[test]
+    fn rejects_truncated_data() {
+        let state = running_state();
+
+        for length in [0, 3, 10, HEADER_SIZE, state.len() - 1] {
+            let (emulator, result) = load(&state[..length], Variant::Chip8);
+            assert_eq!(result, Err(SaveStateError::Truncated), "state cut to {} bytes", length);
+            assert_eq!(emulator.pc(), 0x200);
+        }
+    }
+
+    #[test]
+    fn rejects_other_versions_and_files() {
+        let mut state = running_state();
+        state[4..6].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        assert_eq!(load(&state, Variant::Chip8).1, Err(SaveStateError::UnsupportedVersion(VERSION + 1)));
+
+        state[..4].copy_from_slice(b"C8SV");
+        assert_eq!(load(&state, Variant::Chip8).1, Err(SaveStateError::InvalidMagic));
+    }
+}
@@ -0,0 +1,358 @@
+use std::str::FromStr;
+
+use crate::checksum::{adler32, crc32};
+use crate::emulator::Emulator;
+
+#[doc = "Characters of the plane combinations: none, plane 1, plane 2 and both planes"]
+const ASCII_PIXELS: [char; 4] = ['.', '#', '+', '@'];
+
+#[doc = "Colors of the plane combinations: background, plane 1, plane 2 and both planes"]
+pub type Palette = [[u8; 3]; 4];
+
+#[doc = "Colors of the mono theme, used by screenshots unless another palette is given"]
+pub const DEFAULT_PALETTE: Palette = [
+    [0, 0, 0],
+    [255, 255, 255],
+    [170, 170, 170],
+    [85, 85, 85],
+];
+
+#[doc = "Built-in palettes"]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Theme {
+    Mono,
+    Amber,
+    GreenPhosphor,
+    Lcd,
+    #[doc = "Default colors of the Octo IDE"]
+    Octo,
+}
+
+impl Theme {
+    pub const ALL: [Theme; 5] = [Theme::Mono, Theme::Amber, Theme::GreenPhosphor, Theme::Lcd, Theme::Octo];
+
+    pub fn palette(&self) -> Palette {
+        match self {
+            Theme::Mono => DEFAULT_PALETTE,
+            Theme::Amber => [[0x1A, 0x10, 0x00], [0xFF, 0xB0, 0x00], [0x99, 0x66, 0x00], [0xCC, 0x88, 0x00]],
+            Theme::GreenPhosphor => [[0x0A, 0x1A, 0x0A], [0x33, 0xFF, 0x66], [0x1A, 0x80, 0x33], [0x26, 0xBF, 0x4D]],
+            Theme::Lcd => [[0x9B, 0xBC, 0x0F], [0x0F, 0x38, 0x0F], [0x30, 0x62, 0x30], [0x8B, 0xAC, 0x0F]],
+            Theme::Octo => [[0x99, 0x66, 0x00], [0xFF, 0xCC, 0x00], [0xFF, 0x66, 0x00], [0x66, 0x22, 0x00]],
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Theme::Mono => "mono",
+            Theme::Amber => "amber",
+            Theme::GreenPhosphor => "green",
+            Theme::Lcd => "lcd",
+            Theme::Octo => "octo",
+        }
+    }
+
+    #[doc = "Following theme of ALL, wrapping around"]
+    pub fn next(&self) -> Theme {
+        let index = Theme::ALL.iter().position(|theme| theme == self).unwrap_or(0);
+        Theme::ALL[(index + 1) % Theme::ALL.len()]
+    }
+}
+
+impl FromStr for Theme {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.to_ascii_lowercase();
+
+        Theme::ALL.into_iter().find(|theme| theme.name() == value).ok_or_else(|| {
+            let names: Vec<&str> = Theme::ALL.iter().map(|theme| theme.name()).collect();
+            format!("unknown theme '{}', expected one of {}", value, names.join(", "))
+        })
+    }
+}
+
+#[doc = "Color from RRGGBB hexadecimal, with an optional # or 0x in front"]
+pub fn parse_color(text: &str) -> Result<[u8; 3], String> {
+    let digits = text.trim_start_matches('#').trim_start_matches("0x");
+    let value = u32::from_str_radix(digits, 16).ok().filter(|_| digits.len() == 6 && digits.bytes().all(|digit| digit.is_ascii_hexdigit())).ok_or_else(|| format!("invalid color '{}', expected RRGGBB", text))?;
+
+    Ok([(value >> 16) as u8, (value >> 8) as u8, value as u8])
+}
+
+#[doc = "Four comma separated colors, for the background, plane 1, plane 2 and both planes"]
+pub fn parse_palette(text: &str) -> Result<Palette, String> {
+    let colors = text.split(',').map(|color| parse_color(color.trim())).collect::<Result<Vec<_>, _>>()?;
+
+    colors.try_into().map_err(|_| format!("invalid palette '{}', expected 4 colors", text))
+}
+
+#[doc = "Filtering of the screen when it is scaled to the window"]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScaleFilter {
+    Nearest,
+    Linear,
+}
+
+impl FromStr for ScaleFilter {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "nearest" => Ok(ScaleFilter::Nearest),
+            "linear" => Ok(ScaleFilter::Linear),
+            _ => Err(format!("unknown filter '{}', expected 'nearest' or 'linear'", value)),
+        }
+    }
+}
+
+#[doc = "Area of the window the screen is scaled to, as x, y, width and height"]
+#[doc = ""]
+#[doc = "It is the largest centered area keeping the aspect ratio of the screen, the rest of the window is letterboxed"]
+#[doc = "With integer_scaling the screen is only scaled by whole factors, and never shrunk below its size"]
+pub fn viewport(window_size: (u32, u32), screen_size: (u32, u32), integer_scaling: bool) -> (i32, i32, u32, u32) {
+    let (window_width, window_height) = window_size;
+    let (screen_width, screen_height) = (screen_size.0.max(1), screen_size.1.max(1));
+
+    let mut scale = (window_width as f64 / screen_width as f64).min(window_height as f64 / screen_height as f64);
+    if integer_scaling {
+        scale = scale.floor().max(1.0);
+    }
+
+    let width = (screen_width as f64 * scale).round() as u32;
+    let height = (screen_height as f64 * scale).round() as u32;
+
+    ((window_width as i32 - width as i32) / 2, (window_height as i32 - height as i32) / 2, width, height)
+}
+
+#[doc = "Active screen area as text, one line per row"]
+pub fn to_ascii(emulator: &Emulator) -> String {
+    let (width, height) = emulator.resolution();
+    let video_memory = emulator.video_memory();
+    let mut text = String::with_capacity((width + 1) * height);
+
+    for y in 0..height {
+        for column in video_memory[..width].iter() {
+            text.push(ASCII_PIXELS[column[y] as usize % ASCII_PIXELS.len()]);
+        }
+        text.push('\n');
+    }
+
+    text
+}
+
+#[doc = "Screen hash as written by --dump-screen to a .hash file"]
+pub fn to_hash(emulator: &Emulator) -> String {
+    format!("{:016X}\n", emulator.screen_hash())
+}
+
+#[doc = "Active screen area as an indexed color PNG image, one image pixel per screen pixel"]
+pub fn to_png(emulator: &Emulator, palette: &Palette) -> Vec<u8> {
+    let (width, height) = emulator.resolution();
+    let video_memory = emulator.video_memory();
+
+    // Every scanline starts with its filter type, 0 is no filtering
+    let mut scanlines = Vec::with_capacity((width + 1) * height);
+    for y in 0..height {
+        scanlines.push(0);
+        scanlines.extend(video_memory[..width].iter().map(|column| column[y] % palette.len() as u8));
+    }
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // 8 bit depth, palette color type, deflate, standard filters, no interlacing
+    header.extend_from_slice(&[8, 3, 0, 0, 0]);
+
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    write_chunk(&mut png, b"IHDR", &header);
+    write_chunk(&mut png, b"PLTE", palette.concat().as_slice());
+    write_chunk(&mut png, b"IDAT", &zlib_stored(&scanlines));
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+#[doc = "Wrap the data in a zlib stream made of uncompressed deflate blocks"]
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut stream = vec![0x78, 0x01];
+    let mut blocks = data.chunks(u16::MAX as usize).peekable();
+
+    if blocks.peek().is_none() {
+        stream.extend_from_slice(&[0x01, 0x00, 0x00, 0xFF, 0xFF]);
+    }
+
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let length = block.len() as u16;
+
+        stream.push(last as u8);
+        stream.extend_from_slice(&length.to_le_bytes());
+        stream.extend_from_slice(&(!length).to_le_bytes());
+        stream.extend_from_slice(block);
+    }
+
+    stream.extend_from_slice(&adler32(data).to_be_bytes());
+    stream
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Instruction, Quirks, Variant};
+
+    #[doc = "Emulator with the 0 of the font drawn at the top left corner into the given planes"]
+    fn zero_drawn(variant: Variant, planes: u8) -> Emulator {
+        let mut emulator = Emulator::new(Vec::new(), variant, Quirks::default()).unwrap();
+        // Both planes use the same sprite
+        emulator.write_memory(0x300, &[0xF0, 0x90, 0x90, 0x90, 0xF0, 0xF0, 0x90, 0x90, 0x90, 0xF0]).unwrap();
+        emulator.set_i(0x300);
+
+        if variant.supports_xo_chip() {
+            emulator.execute(&Instruction::Plane { planes }).unwrap();
+        }
+        emulator.execute(&Instruction::DrwVxVyN { x: 0, y: 0, n: 5 }).unwrap();
+        emulator
+    }
+
+    #[test]
+    fn ascii_rows() {
+        let text = to_ascii(&zero_drawn(Variant::Chip8, 1));
+        let rows: Vec<&str> = text.lines().collect();
+
+        assert_eq!(rows.len(), 32);
+        assert!(rows.iter().all(|row| row.len() == 64));
+        assert_eq!(&rows[0][..6], "####..");
+        assert_eq!(&rows[1][..6], "#..#..");
+        assert_eq!(rows[5], ".".repeat(64));
+
+        // Plane 2 and both planes have their own characters
+        assert_eq!(&to_ascii(&zero_drawn(Variant::XoChip, 2))[..5], "++++.");
+        assert_eq!(&to_ascii(&zero_drawn(Variant::XoChip, 3))[..5], "@@@@.");
+    }
+
+    #[doc = "Type and data of every chunk, checking the CRC of each"]
+    fn png_chunks(png: &[u8]) -> Vec<([u8; 4], &[u8])> {
+        let mut chunks = Vec::new();
+        let mut rest = &png[8..];
+
+        while !rest.is_empty() {
+            let length = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+            let (chunk, crc) = rest[4..].split_at(4 + length);
+            assert_eq!(crc32(chunk), u32::from_be_bytes(crc[..4].try_into().unwrap()));
+
+            chunks.push((chunk[..4].try_into().unwrap(), &chunk[4..]));
+            rest = &crc[4..];
+        }
+        chunks
+    }
+
+    #[test]
+    fn png_layout() {
+        let emulator = zero_drawn(Variant::Chip8, 1);
+        let png = to_png(&emulator, &DEFAULT_PALETTE);
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+
+        let chunks = png_chunks(&png);
+        let kinds: Vec<&[u8; 4]> = chunks.iter().map(|(kind, _)| kind).collect();
+        assert_eq!(kinds, [b"IHDR", b"PLTE", b"IDAT", b"IEND"]);
+
+        let header = chunks[0].1;
+        assert_eq!(header[..8], [0, 0, 0, 64, 0, 0, 0, 32]);
+        assert_eq!(header[8..], [8, 3, 0, 0, 0]);
+        assert_eq!(chunks[1].1, DEFAULT_PALETTE.concat());
+
+        // A single stored block with the filter byte and the palette index of every pixel
+        let stream = chunks[2].1;
+        let scanlines = &stream[7..stream.len() - 4];
+        assert_eq!(stream[..3], [0x78, 0x01, 0x01]);
+        let length: u16 = 65 * 32;
+        assert_eq!(scanlines.len(), length as usize);
+        assert_eq!(stream[3..7], [length.to_le_bytes(), (!length).to_le_bytes()].concat());
+        assert_eq!(stream[stream.len() - 4..], adler32(scanlines).to_be_bytes());
+        assert_eq!(scanlines[..6], [0, 1, 1, 1, 1, 0]);
+        assert_eq!(scanlines[65..71], [0, 1, 0, 0, 1, 0]);
+    }
+
+    #[test]
+    fn png_of_a_hires_screen() {
+        let mut emulator = zero_drawn(Variant::XoChip, 3);
+        emulator.execute(&Instruction::High).unwrap();
+        emulator.execute(&Instruction::DrwVxVyN { x: 0, y: 0, n: 5 }).unwrap();
+
+        let png = to_png(&emulator, &DEFAULT_PALETTE);
+        let chunks = png_chunks(&png);
+        assert_eq!(chunks[0].1[..8], [0, 0, 0, 128, 0, 0, 0, 64]);
+        assert_eq!(chunks[2].1[7..13], [0, 3, 3, 3, 3, 0]);
+    }
+
+    #[test]
+    fn hash_dump() {
+        let blank = Emulator::new(Vec::new(), Variant::Chip8, Quirks::default()).unwrap();
+        let drawn = zero_drawn(Variant::Chip8, 1);
+
+        assert_eq!(to_hash(&drawn), format!("{:016X}\n", drawn.screen_hash()));
+        assert_eq!(to_hash(&drawn).len(), 17);
+        assert_ne!(to_hash(&drawn), to_hash(&blank));
+        assert_eq!(to_hash(&drawn), to_hash(&zero_drawn(Variant::Chip8, 1)));
+    }
+
+    #[test]
+    fn viewport_letterboxes() {
+        assert_eq!(viewport((640, 320), (64, 32), false), (0, 0, 640, 320));
+        assert_eq!(viewport((800, 600), (64, 32), false), (0, 100, 800, 400));
+        assert_eq!(viewport((1000, 320), (64, 32), false), (180, 0, 640, 320));
+        assert_eq!(viewport((800, 600), (128, 64), false), (0, 100, 800, 400));
+    }
+
+    #[test]
+    fn viewport_integer_scaling() {
+        assert_eq!(viewport((800, 600), (64, 32), true), (16, 108, 768, 384));
+        assert_eq!(viewport((800, 600), (128, 64), true), (16, 108, 768, 384));
+        assert_eq!(viewport((640, 320), (64, 32), true), (0, 0, 640, 320));
+
+        // A window smaller than the screen only shrinks it without integer scaling, otherwise it's cropped
+        assert_eq!(viewport((50, 20), (64, 32), false), (5, 0, 40, 20));
+        assert_eq!(viewport((50, 20), (64, 32), true), (-7, -6, 64, 32));
+    }
+
+    #[test]
+    fn colors() {
+        assert_eq!(parse_color("FFB000"), Ok([0xFF, 0xB0, 0x00]));
+        assert_eq!(parse_color("#1a2b3c"), Ok([0x1A, 0x2B, 0x3C]));
+        assert_eq!(parse_color("0x000001"), Ok([0x00, 0x00, 0x01]));
+
+        for text in ["", "#", "FFF", "FFB0000", "GGGGGG", "+FFFFF", "FF FF00"] {
+            assert_eq!(parse_color(text), Err(format!("invalid color '{}', expected RRGGBB", text)));
+        }
+    }
+
+    #[test]
+    fn palettes() {
+        // One color per plane combination
+        assert_eq!(parse_palette("000000,FFFFFF,aaaaaa,555555"), Ok(DEFAULT_PALETTE));
+        assert_eq!(parse_palette("#000000, #FFFFFF, #AAAAAA, #555555"), Ok(DEFAULT_PALETTE));
+
+        assert_eq!(parse_palette("000000,FFFFFF"), Err("invalid palette '000000,FFFFFF', expected 4 colors".to_string()));
+        assert_eq!(parse_palette("000000,FFFFFF,AAAAAA,555555,"), Err("invalid color '', expected RRGGBB".to_string()));
+        assert_eq!(parse_palette("000000,000000,000000,000000,000000"), Err("invalid palette '000000,000000,000000,000000,000000', expected 4 colors".to_string()));
+        assert_eq!(parse_palette("000000,FFFFFF,AAAAAA,55555X"), Err("invalid color '55555X', expected RRGGBB".to_string()));
+    }
+
+    #[test]
+    fn themes() {
+        assert_eq!("Amber".parse(), Ok(Theme::Amber));
+        assert!("blue".parse::<Theme>().is_err());
+        assert_eq!(Theme::Octo.next(), Theme::Mono);
+        assert!(Theme::ALL.iter().all(|theme| theme.name().parse() == Ok(*theme)));
+    }
+}
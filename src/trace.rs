@@ -0,0 +1,238 @@
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::io::Write;
+use std::str::FromStr;
+
+use crate::disassembler;
+use crate::emulator::Emulator;
+use crate::error::{EmulatorError, StepOutcome};
+
+#[doc = "Inclusive range of addresses, parsed from hexadecimal `start-end` or a single address"]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AddressRange {
+    pub start: u16,
+    pub end: u16,
+}
+
+impl AddressRange {
+    pub fn contains(&self, address: u16) -> bool {
+        (self.start..=self.end).contains(&address)
+    }
+}
+
+impl FromStr for AddressRange {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let parse = |text: &str| u16::from_str_radix(text.trim_start_matches("0x"), 16).map_err(|_| format!("invalid address '{}'", text));
+
+        let (start, end) = match value.split_once('-') {
+            Some((start, end)) => (parse(start)?, parse(end)?),
+            None => (parse(value)?, parse(value)?),
+        };
+
+        if start > end {
+            return Err(format!("'{}' ends before it starts", value));
+        }
+
+        Ok(Self { start, end })
+    }
+}
+
+#[doc = "State before an instruction, compared with the state after it to find what changed"]
+pub(crate) struct TraceSnapshot {
+    cycle: u64,
+    pc: u16,
+    #[doc = "Bytes of the instruction, taken before it could overwrite itself"]
+    code: Vec<u8>,
+    registers: [u8; 16],
+    i: u16,
+    sp: u8,
+}
+
+#[doc = "Writes a line per executed instruction: cycle, PC, opcode, disassembly and the registers it changed"]
+#[doc = ""]
+#[doc = "In ring buffer mode only the last lines are kept, and written once the emulator fails"]
+pub struct Tracer {
+    #[doc = "None after a write failed, the trace stops there"]
+    output: Option<Box<dyn Write>>,
+    range: Option<AddressRange>,
+    ring_capacity: Option<usize>,
+    ring: VecDeque<String>,
+}
+
+impl Tracer {
+    #[doc = "Only instructions whose address is in range are traced, errors are always written"]
+    pub fn new(output: Box<dyn Write>, range: Option<AddressRange>, ring_capacity: Option<usize>) -> Self {
+        Self {
+            output: Some(output),
+            range,
+            ring_capacity,
+            ring: VecDeque::new(),
+        }
+    }
+
+    pub(crate) fn before(&self, emulator: &Emulator) -> TraceSnapshot {
+        TraceSnapshot {
+            cycle: emulator.cycles(),
+            pc: emulator.pc(),
+            code: emulator.memory().iter().skip(emulator.pc() as usize).take(4).copied().collect(),
+            registers: *emulator.registers(),
+            i: emulator.i(),
+            sp: emulator.sp(),
+        }
+    }
+
+    pub(crate) fn after(&mut self, before: &TraceSnapshot, emulator: &Emulator, result: &Result<StepOutcome, EmulatorError>) {
+        if self.range.is_none_or(|range| range.contains(before.pc)) {
+            let line = format_line(before, emulator);
+
+            match self.ring_capacity {
+                Some(capacity) => {
+                    if self.ring.len() >= capacity {
+                        self.ring.pop_front();
+                    }
+                    if capacity > 0 {
+                        self.ring.push_back(line);
+                    }
+                },
+                None => self.write(&line),
+            }
+        }
+
+        match result {
+            Err(error) => {
+                while let Some(line) = self.ring.pop_front() {
+                    self.write(&line);
+                }
+                self.write(&format!("error: {}", error));
+                self.flush();
+            },
+            Ok(StepOutcome::Exit) => self.flush(),
+            Ok(_) => {},
+        }
+    }
+
+    fn write(&mut self, line: &str) {
+        if let Some(output) = &mut self.output {
+            if writeln!(output, "{}", line).is_err() {
+                self.output = None;
+            }
+        }
+    }
+
+    fn flush(&mut self) {
+        if let Some(output) = &mut self.output {
+            if output.flush().is_err() {
+                self.output = None;
+            }
+        }
+    }
+}
+
+fn format_line(before: &TraceSnapshot, emulator: &Emulator) -> String {
+    let (opcode, text) = match before.code.as_slice() {
+        bytes @ [first, second, ..] => (format!("{:02X}{:02X}", first, second), disassembler::disassemble_instruction(bytes).0),
+        _ => ("????".to_string(), String::new()),
+    };
+
+    let mut line = format!("{:>10} {:03X} {} {:<20}", before.cycle, before.pc, opcode, text);
+
+    for (x, (old, new)) in before.registers.iter().zip(emulator.registers()).enumerate() {
+        if old != new {
+            let _ = write!(line, " V{:X}={:02X}->{:02X}", x, old, new);
+        }
+    }
+    if before.i != emulator.i() {
+        let _ = write!(line, " I={:03X}->{:03X}", before.i, emulator.i());
+    }
+    if before.sp != emulator.sp() {
+        let _ = write!(line, " SP={:X}->{:X}", before.sp, emulator.sp());
+    }
+
+    line.trim_end().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::{Quirks, Variant};
+
+    #[doc = "Output shared with the test, the tracer owns its writer"]
+    #[derive(Clone, Default)]
+    struct SharedOutput(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedOutput {
+        fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(bytes);
+            Ok(bytes.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SharedOutput {
+        fn lines(&self) -> Vec<String> {
+            String::from_utf8(self.0.borrow().clone()).unwrap().lines().map(str::to_string).collect()
+        }
+    }
+
+    #[doc = "Run the given number of cycles of the rom with a tracer, returning what it wrote"]
+    fn trace(rom: &[u8], cycles: usize, range: Option<AddressRange>, ring_capacity: Option<usize>) -> Vec<String> {
+        let output = SharedOutput::default();
+        let mut emulator = Emulator::new(rom.to_vec(), Variant::Chip8, Quirks::default()).unwrap();
+        emulator.set_tracer(Some(Tracer::new(Box::new(output.clone()), range, ring_capacity)));
+
+        for _ in 0..cycles {
+            let _ = emulator.next_cycle();
+        }
+        output.lines()
+    }
+
+    // 0x200: LD V0, 0x2A / LD I, 0x300 / CALL 0x208 / JP 0x206
+    // 0x208: ADD V0, 1 / RET
+    const ROM: [u8; 12] = [0x60, 0x2A, 0xA3, 0x00, 0x22, 0x08, 0x12, 0x06, 0x70, 0x01, 0x00, 0xEE];
+
+    #[test]
+    fn lines() {
+        // Same as --trace writes for the rom
+        assert_eq!(trace(&ROM, 7, None, None), [
+            "         0 200 602A LD V0, 0x2A          V0=00->2A",
+            "         1 202 A300 LD I, 0x300          I=000->300",
+            "         2 204 2208 CALL 0x208           SP=0->1",
+            "         3 208 7001 ADD V0, 0x01         V0=2A->2B",
+            "         4 20A 00EE RET                  SP=1->0",
+            "         5 206 1206 JP 0x206",
+            "         6 206 1206 JP 0x206",
+        ]);
+    }
+
+    #[test]
+    fn lines_in_range() {
+        let range = Some("208-20A".parse().unwrap());
+        assert_eq!(trace(&ROM, 7, range, None), [
+            "         3 208 7001 ADD V0, 0x01         V0=2A->2B",
+            "         4 20A 00EE RET                  SP=1->0",
+        ]);
+    }
+
+    #[test]
+    fn ring_keeps_the_last_lines_until_an_error() {
+        // ADD V0, 1 five times, then an unknown opcode
+        let rom = [[0x70, 0x01].repeat(5), vec![0x00, 0x00]].concat();
+
+        assert_eq!(trace(&rom, 5, None, Some(3)), Vec::<String>::new());
+        // The failing instruction is one of the kept lines
+        assert_eq!(trace(&rom, 6, None, Some(3)), [
+            "         3 206 7001 ADD V0, 0x01         V0=03->04",
+            "         4 208 7001 ADD V0, 0x01         V0=04->05",
+            "         5 20A 0000 DW 0x0000",
+            "error: unknown opcode 0x0000 at 0x20A",
+        ]);
+    }
+}
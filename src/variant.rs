@@ -0,0 +1,67 @@
+use std::str::FromStr;
+
+use crate::instruction::Instruction;
+
+#[doc = "CHIP-8 dialect, decides which instructions are available"]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Variant {
+    Chip8,
+    SuperChip,
+    XoChip,
+}
+
+impl Variant {
+    #[doc = "Whether the SUPER-CHIP 1.1 instructions (hi-res, scrolling, big font, RPL flags) are available"]
+    pub fn supports_super_chip(&self) -> bool {
+        matches!(self, Variant::SuperChip | Variant::XoChip)
+    }
+
+    #[doc = "Whether the XO-CHIP instructions (long I load, bitplanes, register ranges, audio pattern) are available"]
+    pub fn supports_xo_chip(&self) -> bool {
+        matches!(self, Variant::XoChip)
+    }
+
+    #[doc = "Whether the instruction is available in this variant"]
+    pub fn supports(&self, instruction: &Instruction) -> bool {
+        (!instruction.is_super_chip() || self.supports_super_chip()) && (!instruction.is_xo_chip() || self.supports_xo_chip())
+    }
+
+    #[doc = "Number identifying the variant in save states and movies"]
+    pub fn id(&self) -> u8 {
+        match self {
+            Variant::Chip8 => 0,
+            Variant::SuperChip => 1,
+            Variant::XoChip => 2,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(Variant::Chip8),
+            1 => Some(Variant::SuperChip),
+            2 => Some(Variant::XoChip),
+            _ => None,
+        }
+    }
+
+    #[doc = "Size of the addressable memory in bytes"]
+    pub fn memory_size(&self) -> usize {
+        match self {
+            Variant::Chip8 | Variant::SuperChip => 0x1000,
+            Variant::XoChip => 0x10000,
+        }
+    }
+}
+
+impl FromStr for Variant {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "chip8" | "chip-8" => Ok(Variant::Chip8),
+            "schip" | "super-chip" => Ok(Variant::SuperChip),
+            "xochip" | "xo-chip" => Ok(Variant::XoChip),
+            _ => Err(format!("unknown variant '{}', expected 'chip8', 'schip' or 'xochip'", value)),
+        }
+    }
+}